@@ -0,0 +1,108 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+};
+
+/// Name of the cache file, stored in the project's root
+pub const CACHE_FILE: &str = ".wwwfmt.cache";
+
+/// Content of the cache file
+#[derive(Serialize, Deserialize, Default)]
+struct Entries {
+    /// Version of wwwfmt that wrote the cache
+    version: String,
+    /// Hash of the configuration and of the formatting mode
+    settings: String,
+    /// Hash of the content of each file, by path relative to the project's root
+    files: BTreeMap<String, String>,
+}
+
+/// Cache of the files formatted in a previous run, used to skip the unchanged ones.
+///
+/// A file is unchanged if its content has the same hash as the one recorded in the cache. When
+/// formatting in place the hash of the formatted content is recorded, otherwise the hash of the
+/// source. The whole cache is invalidated if the configuration, the mode or wwwfmt's version
+/// change.
+pub struct Cache {
+    root: PathBuf,
+    old: Entries,
+    new: Mutex<BTreeMap<String, String>>,
+}
+
+/// Hashes some content
+fn hash(data: &[u8]) -> String {
+    format!("{:016x}", xxh3_64(data))
+}
+
+impl Cache {
+    /// Loads the cache of a project. A missing, unreadable or outdated cache is considered empty.
+    pub fn open(root: &Path, config: &Config, minify: bool, inplace: bool) -> Self {
+        let config = toml::to_string(config).expect("Config serialization failed");
+        let settings = hash(format!("{config}\nminify={minify}\ninplace={inplace}").as_bytes());
+        let version = env!("CARGO_PKG_VERSION").to_owned();
+        let old = fs::read_to_string(root.join(CACHE_FILE))
+            .ok()
+            .and_then(|s| toml::from_str::<Entries>(&s).ok())
+            .filter(|e| e.version == version && e.settings == settings)
+            .unwrap_or(Entries {
+                version,
+                settings,
+                files: BTreeMap::new(),
+            });
+        Self {
+            root: root.to_path_buf(),
+            old,
+            new: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the key of a file in the cache
+    fn key(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Returns whether `data` is the content recorded for a file in the previous run. If so the
+    /// file is kept in the cache.
+    pub fn is_fresh(&self, path: &Path, data: &[u8]) -> bool {
+        let key = self.key(path);
+        let hash = hash(data);
+        let fresh = self.old.files.get(&key) == Some(&hash);
+        if fresh {
+            self.insert_hash(key, hash);
+        }
+        fresh
+    }
+
+    /// Records the content of a file
+    pub fn insert(&self, path: &Path, data: &[u8]) {
+        self.insert_hash(self.key(path), hash(data));
+    }
+
+    fn insert_hash(&self, key: String, hash: String) {
+        self.new.lock().expect("Cache poisoned").insert(key, hash);
+    }
+
+    /// Writes the files recorded in this run to the cache file
+    pub fn save(self) -> Result<()> {
+        let entries = Entries {
+            files: self.new.into_inner().expect("Cache poisoned"),
+            ..self.old
+        };
+        let path = self.root.join(CACHE_FILE);
+        let data = toml::to_string(&entries).expect("Cache serialization failed");
+        fs::write(&path, data).map_err(|e| Error::io(Some(&path), "Failed to write cache", e))
+    }
+}
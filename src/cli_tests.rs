@@ -0,0 +1,92 @@
+use std::fs;
+
+use argh::FromArgs;
+use testdir::testdir;
+use wwwfmt::{Error, Language, Mode, conf::Config, format_str};
+
+use crate::{Cli, fmt_stdin, lsp};
+
+#[test]
+fn test_stdin() {
+    let config = Config::default();
+    let cli = |args: &[&str]| Cli::from_args(&["wwwfmt"], args).unwrap();
+    let out = fmt_stdin(
+        "a {\n  color : red ;\n}\n".as_bytes(),
+        &cli(&["-u", "--stdin", "--lang", "CSS"]),
+        &config,
+        true,
+    )
+    .expect("stdin minify failed");
+    assert_eq!(out, "a{color:red}");
+    let out = fmt_stdin(
+        "{\"a\":1}".as_bytes(),
+        &cli(&["-p", "--stdin-filepath", "dir/a.json"]),
+        &config,
+        false,
+    )
+    .expect("stdin prettify failed");
+    assert_eq!(out, "{\n  \"a\": 1\n}\n");
+
+    let e = fmt_stdin("".as_bytes(), &cli(&["-p", "--stdin"]), &config, false)
+        .expect_err("stdin formatted without a language");
+    assert!(e.contains("--lang"), "{e}");
+    let e = fmt_stdin(
+        "".as_bytes(),
+        &cli(&["-p", "--stdin", "--lang", "rs"]),
+        &config,
+        false,
+    )
+    .expect_err("Unknown language formatted");
+    assert_eq!(e, "Unknown language: rs");
+    // Errors are reported with the path of the source
+    let e = fmt_stdin(
+        "{\n\"a\" 1}".as_bytes(),
+        &cli(&["-p", "--stdin-filepath", "a.json"]),
+        &config,
+        false,
+    )
+    .expect_err("Invalid JSON formatted");
+    assert!(e.contains("a.json:2:"), "{e}");
+}
+
+#[test]
+fn test_lsp_edits() {
+    let old = "a\nb\nc\n";
+    let new = "A\nb\nC\n";
+    assert_eq!(lsp::edits(old, new, None).len(), 2);
+    let edits = lsp::edits(old, new, Some((2, 2)));
+    assert_eq!(edits.len(), 1);
+    assert_eq!(edits[0].range.start.line, 2);
+    assert_eq!(edits[0].range.end.line, 3);
+    assert_eq!(edits[0].new_text, "C\n");
+}
+
+#[test]
+fn test_lsp_errors() {
+    let config = Config::default();
+    // Columns are in UTF-16 code units
+    let src = "let s = '\u{1F600}'; let b = ;\n";
+    let e = format_str(src, Language::JavaScript, &config, Mode::Prettify)
+        .expect_err("Invalid JavaScript formatted");
+    let range = lsp::diagnostic(&e, src).range;
+    assert_eq!((range.start.line, range.start.character), (0, 22));
+    assert_eq!((range.end.line, range.end.character), (0, 23));
+
+    // Errors of the configuration are reported
+    let path = testdir!();
+    fs::write(path.join(".wwwfmt.toml"), "uglify_outdir = 1\n").unwrap();
+    let uri = lsp_types::Url::from_file_path(path.join("a.css")).unwrap();
+    let Err(e) = lsp::document_info(&uri) else {
+        panic!("Invalid config loaded");
+    };
+    assert!(matches!(e, Error::Config { .. }));
+    let diagnostic = lsp::diagnostic(&e, "a {}\n");
+    assert_eq!(diagnostic.range, Default::default());
+    assert!(
+        diagnostic.message.contains(".wwwfmt.toml"),
+        "{}",
+        diagnostic.message
+    );
+    let uri = lsp_types::Url::from_file_path(path.join("a.rs")).unwrap();
+    assert!(lsp::document_info(&uri).unwrap().is_none());
+}
@@ -1,3 +1,4 @@
+use crate::error::{Error, Result, Span};
 use crate::files::{get_currdir, revtraverse};
 use serde::{Deserialize, Serialize};
 use std::fs::File;
@@ -11,6 +12,56 @@ pub enum IndentKind {
     Space,
 }
 
+/// How the source maps of minified JavaScript and CSS files are emitted
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SourceMap {
+    /// No source map
+    #[default]
+    None,
+    /// Writes the source map in a .map file next to the output
+    File,
+    /// Writes the source map in a .map file and appends its `sourceMappingURL` to the output
+    Linked,
+    /// Appends the source map to the output as a data URL
+    Inline,
+}
+
+/// How JSX is transformed when transpiling
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JsxRuntime {
+    /// Calls the pragma (`React.createElement` by default)
+    Classic,
+    /// Imports the JSX functions from the import source (`react` by default)
+    #[default]
+    Automatic,
+}
+
+/// Which optional tags are omitted when minifying HTML. Tags are omitted only where the HTML
+/// spec allows it, so the parsed document doesn't change
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OmitTags {
+    /// Every tag is written
+    #[default]
+    None,
+    /// Omits optional end tags (e.g. `</li>`, `</p>`, `</td>`, `</body>`)
+    EndTags,
+    /// Also omits the start tags of `html`, `head` and `body`
+    All,
+}
+
+/// How the whitespace of HTML text is collapsed when minifying
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Whitespace {
+    /// Collapses the whitespace at the start and at the end of text to a single space
+    #[default]
+    Trim,
+    /// Also removes the whitespace next to block-level elements, where it can't render
+    Conservative,
+    /// Also collapses the whitespace inside of text and removes the whitespace around line breaks
+    /// (`br`). Assumes that CSS doesn't change how whitespace is rendered
+    Aggressive,
+}
+
 /// Specifies the configuration for HTML
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Html {
@@ -23,8 +74,46 @@ pub struct Html {
     pub prettify_noindent_tags: Vec<String>,
     /// Remove comments from HTML. Valid when minifying
     pub uglify_rm_comments: bool,
-    /// Format tag's attributes
+    /// Format tag's attributes: the JavaScript of event handlers (`on*`) and the CSS of `style`
     pub fmt_attrs: bool,
+    /// Write attribute values without quotes where possible. Valid when minifying
+    #[serde(default)]
+    pub uglify_unquote_attrs: bool,
+    /// Write boolean attributes and empty values without value (`disabled="disabled"` becomes
+    /// `disabled`). Valid when minifying
+    #[serde(default)]
+    pub uglify_collapse_bool_attrs: bool,
+    /// Remove attributes set to their default value (e.g. `type="text"` on `input`). CSS
+    /// selectors matching them won't match anymore. Valid when minifying
+    #[serde(default)]
+    pub uglify_rm_default_attrs: bool,
+    /// Collapse the whitespace inside `class` attributes. Valid when minifying
+    #[serde(default)]
+    pub uglify_trim_class: bool,
+    /// Omit the optional tags. Valid when minifying
+    #[serde(default)]
+    pub uglify_omit_tags: OmitTags,
+    /// How whitespace is collapsed. Valid when minifying
+    #[serde(default)]
+    pub uglify_collapse_whitespace: Whitespace,
+    /// Tags whose whitespace is significant, their content is written as it is
+    #[serde(default = "preserve_tags")]
+    pub preserve_tags: Vec<String>,
+    /// Classes making the whitespace of an element significant (e.g. styled with
+    /// `white-space: pre`), its content is written as it is
+    #[serde(default)]
+    pub preserve_classes: Vec<String>,
+    /// Attributes making the whitespace of an element significant, its content is written as it is
+    #[serde(default)]
+    pub preserve_attrs: Vec<String>,
+}
+
+/// Default whitespace-sensitive tags
+fn preserve_tags() -> Vec<String> {
+    ["pre", "textarea", "listing", "xmp", "plaintext"]
+        .into_iter()
+        .map(|s| s.to_owned())
+        .collect()
 }
 
 impl Default for Html {
@@ -42,6 +131,15 @@ impl Default for Html {
             .collect(),
             uglify_rm_comments: true,
             fmt_attrs: true,
+            uglify_unquote_attrs: false,
+            uglify_collapse_bool_attrs: false,
+            uglify_rm_default_attrs: false,
+            uglify_trim_class: false,
+            uglify_omit_tags: OmitTags::None,
+            uglify_collapse_whitespace: Whitespace::Trim,
+            preserve_tags: preserve_tags(),
+            preserve_classes: Vec::new(),
+            preserve_attrs: Vec::new(),
         }
     }
 }
@@ -51,12 +149,16 @@ impl Default for Html {
 pub struct Css {
     /// Optimize CSS while minifying
     pub uglify_optimize: bool,
+    /// Source map of minified files. Not generated for styles embedded in HTML
+    #[serde(default)]
+    pub uglify_source_map: SourceMap,
 }
 
 impl Default for Css {
     fn default() -> Self {
         Self {
             uglify_optimize: true,
+            uglify_source_map: SourceMap::None,
         }
     }
 }
@@ -75,6 +177,25 @@ pub struct JavaScript {
     pub uglify_drop_debugger: bool,
     /// Drop console calls in code when minifying
     pub uglify_drop_console: bool,
+    /// Source map of minified files. Not generated for scripts embedded in HTML
+    #[serde(default)]
+    pub uglify_source_map: SourceMap,
+    /// Strip the types of TypeScript and transform JSX when minifying. The output is written as
+    /// .js (.mjs/.cjs for .mts/.cts), so it can't be written in place
+    #[serde(default)]
+    pub uglify_transpile: bool,
+    /// JSX runtime used when transpiling
+    #[serde(default)]
+    pub uglify_jsx_runtime: JsxRuntime,
+    /// Function called by the classic runtime for JSX elements
+    #[serde(default)]
+    pub uglify_jsx_pragma: Option<String>,
+    /// Function called by the classic runtime for JSX fragments
+    #[serde(default)]
+    pub uglify_jsx_pragma_frag: Option<String>,
+    /// Module the automatic runtime imports the JSX functions from
+    #[serde(default)]
+    pub uglify_jsx_import_source: Option<String>,
 }
 
 impl Default for JavaScript {
@@ -85,6 +206,58 @@ impl Default for JavaScript {
             uglify_mangle: true,
             uglify_drop_debugger: false,
             uglify_drop_console: false,
+            uglify_source_map: SourceMap::None,
+            uglify_transpile: false,
+            uglify_jsx_runtime: JsxRuntime::Automatic,
+            uglify_jsx_pragma: None,
+            uglify_jsx_pragma_frag: None,
+            uglify_jsx_import_source: None,
+        }
+    }
+}
+
+/// Specifies the configuration for JSON (and JSONC)
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Json {
+    /// Indent character used when indenting. Valid when prettifying
+    pub prettify_indent_kind: IndentKind,
+    /// How many times the indent char is repeated. Valid when prettifying
+    pub prettify_indent_num: usize,
+    /// Sort the keys of objects
+    pub sort_keys: bool,
+    /// Remove the comments of JSONC files. Valid when minifying
+    pub uglify_rm_comments: bool,
+}
+
+impl Default for Json {
+    fn default() -> Self {
+        Self {
+            prettify_indent_kind: IndentKind::Space,
+            prettify_indent_num: 2,
+            sort_keys: false,
+            uglify_rm_comments: true,
+        }
+    }
+}
+
+/// Specifies the configuration for XML and SVG. They are indented as HTML
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Xml {
+    /// Remove comments. Valid when minifying
+    pub uglify_rm_comments: bool,
+    /// Optimize SVG while minifying: remove editor metadata and unused namespaces, shorten path
+    /// data and numbers, remove attributes set to their default value and minify styles
+    pub uglify_svg_optimize: bool,
+    /// Round the numbers of SVG geometry to this many decimals. Valid when optimizing SVG
+    pub uglify_svg_precision: Option<usize>,
+}
+
+impl Default for Xml {
+    fn default() -> Self {
+        Self {
+            uglify_rm_comments: true,
+            uglify_svg_optimize: true,
+            uglify_svg_precision: None,
         }
     }
 }
@@ -95,12 +268,28 @@ pub struct Config {
     pub html: Html,
     pub css: Css,
     pub javascript: JavaScript,
+    #[serde(default)]
+    pub json: Json,
+    #[serde(default)]
+    pub xml: Xml,
     /// Output directory of minified files
     pub uglify_outdir: Option<String>,
     /// Output directory of prettified files
     pub prettify_outdir: Option<String>,
-    /// Ignore these paths
+    /// Ignore the paths matching these patterns (gitignore syntax, relative to the project's root)
     pub ignore_path: Vec<String>,
+    /// Only format the files matching these patterns (gitignore syntax, relative to the project's
+    /// root). If empty every file is formatted
+    #[serde(default)]
+    pub include_path: Vec<String>,
+    /// Skip the files ignored by `.gitignore`, `.ignore` and `.wwwfmtignore` files, and the `.git`
+    /// directory
+    #[serde(default = "enabled")]
+    pub respect_ignore_files: bool,
+    /// Browsers that must support the minified CSS and JavaScript (browserslist query, e.g.
+    /// "> 0.5%, last 2 versions"). Newer syntax is lowered and vendor prefixes are added
+    #[serde(default)]
+    pub targets: Option<String>,
 }
 
 impl Default for Config {
@@ -110,41 +299,61 @@ impl Default for Config {
             html: Html::default(),
             css: Css::default(),
             javascript: JavaScript::default(),
+            json: Json::default(),
+            xml: Xml::default(),
             uglify_outdir: Some("wwwugly".into()),
             prettify_outdir: None,
             ignore_path: vec!["wwwugly"].into_iter().map(|s| s.to_owned()).collect(),
+            include_path: Vec::new(),
+            respect_ignore_files: true,
+            targets: None,
         }
     }
 }
 
+/// Default value of switches added after the first release, so that old config files still parse
+fn enabled() -> bool {
+    true
+}
+
 impl Config {
     /// Finds the configuration file, opens it and returns the parsed file with the directory
-    pub fn find() -> Result<(Self, PathBuf), String> {
-        let path = revtraverse(get_currdir()?, ".wwwfmt.toml")
-            .map_err(|e| format!("Failed to find .wwwfmt.toml: {e}"))?;
+    pub fn find() -> Result<(Self, PathBuf)> {
+        Self::find_from(get_currdir()?)
+    }
+
+    /// Same as [`Config::find`], but starts looking from `dir` instead of the current directory
+    pub fn find_from(dir: PathBuf) -> Result<(Self, PathBuf)> {
+        let path = revtraverse(dir, ".wwwfmt.toml")
+            .map_err(|e| Error::io(None::<PathBuf>, "Failed to find .wwwfmt.toml", e))?;
         Ok((Self::open(&path)?, path))
     }
 
     /// Opens config file from path
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
         let mut buf = String::new();
         File::open(path)
-            .map_err(|e| format!("Failed to open config file: {e}"))?
+            .map_err(|e| Error::io(Some(path), "Failed to open config file", e))?
             .read_to_string(&mut buf)
-            .map_err(|e| format!("Failed to read config file: {e}"))?;
-        toml::from_str(&buf).map_err(|e| format!("Config error: {e}"))
+            .map_err(|e| Error::io(Some(path), "Failed to read config file", e))?;
+        toml::from_str(&buf).map_err(|e| Error::Config {
+            path: Some(path.to_path_buf()),
+            span: e.span().map(|s| Span::from_offset(&buf, s.start)),
+            msg: format!("Config error: {}", e.message()),
+        })
     }
 
     /// Writes the default configuration to the current working directory
-    pub fn write_default() -> Result<(), String> {
+    pub fn write_default() -> Result<()> {
         let mut path = get_currdir()?;
         path.push(".wwwfmt.toml");
         let config =
             toml::to_string(&Self::default()).expect("Default config serialization failed");
-        File::create(path)
-            .map_err(|e| format!("Failed to create file: {e}"))?
+        File::create(&path)
+            .map_err(|e| Error::io(Some(&path), "Failed to create file", e))?
             .write_all(config.as_bytes())
-            .map_err(|e| format!("Failed to write config: {e}"))?;
+            .map_err(|e| Error::io(Some(&path), "Failed to write config", e))?;
         Ok(())
     }
 }
@@ -1,36 +1,136 @@
-use std::path::PathBuf;
-
 use lightningcss::{
+    error::Error as CssError,
     printer::PrinterOptions,
-    stylesheet::{MinifyOptions, ParserOptions, StyleSheet},
+    stylesheet::{MinifyOptions, ParserOptions, StyleAttribute, StyleSheet},
+    targets::{Browsers, Targets},
 };
+use parcel_sourcemap::{SourceMap, SourceMapError};
 
-use crate::{config::Config, files};
+use crate::{
+    config::Config,
+    error::{Error, Result, Span},
+};
 
-pub fn fmt_str(src: &str, config: &Config, minify: bool) -> Result<String, String> {
-    let mut stylesheet = StyleSheet::parse(src, ParserOptions::default())
-        .map_err(|e| format!("Failed to parse CSS: {e}"))?;
+/// Converts a lightningcss error to an error with the given message
+fn error<T: std::fmt::Display>(e: CssError<T>, msg: &str) -> Error {
+    Error::Css {
+        path: None,
+        span: e.loc.as_ref().map(|loc| Span {
+            line: loc.line as usize + 1,
+            col: loc.column as usize,
+            len: 1,
+        }),
+        msg: format!("{msg}: {}", e.kind),
+        help: None,
+    }
+}
+
+pub fn fmt_str(src: &str, config: &Config, minify: bool) -> Result<String> {
+    generate(src, config, minify, None).map(|(code, _)| code)
+}
+
+/// Formats the declarations of a `style` attribute
+pub fn fmt_declarations(src: &str, config: &Config, minify: bool) -> Result<String> {
+    let mut declarations = StyleAttribute::parse(src, ParserOptions::default())
+        .map_err(|e| error(e, "Failed to parse CSS"))?;
+    let targets = if minify {
+        targets(config)?
+    } else {
+        Targets::default()
+    };
     if minify && config.css.uglify_optimize {
-        stylesheet
-            .minify(MinifyOptions::default())
-            .map_err(|e| format!("Failed to optimize CSS: {e}"))?;
+        declarations.minify(MinifyOptions {
+            targets,
+            ..MinifyOptions::default()
+        });
     }
-    Ok(stylesheet
+    declarations
         .to_css(PrinterOptions {
             minify,
+            targets,
             ..PrinterOptions::default()
         })
-        .map_err(|e| format!("Failed to minify CSS: {e}"))?
-        .code)
+        .map(|res| res.code)
+        .map_err(|e| Error::Css {
+            path: None,
+            span: None,
+            msg: format!("Failed to minify CSS: {e}"),
+            help: None,
+        })
 }
 
-pub fn fmt(
-    path: &PathBuf,
-    out_path: Option<PathBuf>,
+/// Minifies a source and returns the code with its source map (as JSON). `source` is the path of
+/// the source as written in the map.
+pub fn minify_with_map(src: &str, config: &Config, source: &str) -> Result<(String, String)> {
+    let (code, map) = generate(src, config, true, Some(source))?;
+    Ok((code, map.expect("Source map not generated")))
+}
+
+/// Returns the browsers the minified CSS must support
+fn targets(config: &Config) -> Result<Targets> {
+    let Some(query) = &config.targets else {
+        return Ok(Targets::default());
+    };
+    Browsers::from_browserslist([query])
+        .map(Targets::from)
+        .map_err(|e| Error::Config {
+            path: None,
+            span: None,
+            msg: format!("Invalid targets: {e}"),
+        })
+}
+
+fn generate(
+    src: &str,
     config: &Config,
     minify: bool,
-) -> Result<(), String> {
-    let file = files::read(path)?;
-    let fmted = fmt_str(&file, config, minify)?;
-    files::write(path, out_path, fmted.as_bytes())
+    source: Option<&str>,
+) -> Result<(String, Option<String>)> {
+    let mut stylesheet = StyleSheet::parse(src, ParserOptions::default())
+        .map_err(|e| error(e, "Failed to parse CSS"))?;
+    let targets = if minify {
+        targets(config)?
+    } else {
+        Targets::default()
+    };
+    if minify && config.css.uglify_optimize {
+        stylesheet
+            .minify(MinifyOptions {
+                targets,
+                ..MinifyOptions::default()
+            })
+            .map_err(|e| error(e, "Failed to optimize CSS"))?;
+    }
+    let mut map = source.map(|source| {
+        let mut map = SourceMap::new("/");
+        map.add_source(source);
+        map
+    });
+    if let Some(map) = &mut map {
+        map.set_source_content(0, src).map_err(map_error)?;
+    }
+    let code = stylesheet
+        .to_css(PrinterOptions {
+            minify,
+            source_map: map.as_mut(),
+            targets,
+            ..PrinterOptions::default()
+        })
+        .map_err(|e| error(e, "Failed to minify CSS"))?
+        .code;
+    let map = match map {
+        Some(mut map) => Some(map.to_json(None).map_err(map_error)?),
+        None => None,
+    };
+    Ok((code, map))
+}
+
+/// Converts a source map error
+fn map_error(e: SourceMapError) -> Error {
+    Error::Css {
+        path: None,
+        span: None,
+        msg: format!("Failed to generate source map: {e}"),
+        help: None,
+    }
 }
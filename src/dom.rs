@@ -0,0 +1,357 @@
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    rc::{Rc, Weak},
+};
+
+use html5ever::{
+    Attribute, LocalName, ParseOpts, QualName,
+    interface::{ElementFlags, NodeOrText, QuirksMode, TreeSink},
+    ns, parse_document, parse_fragment,
+    tendril::{StrTendril, TendrilSink},
+};
+
+/// Reference to a node of the tree
+pub type Handle = Rc<Node>;
+
+/// Kind of node and its data
+pub enum NodeData {
+    Document,
+    Doctype {
+        name: StrTendril,
+        public_id: StrTendril,
+        system_id: StrTendril,
+    },
+    Text {
+        contents: RefCell<StrTendril>,
+    },
+    Comment {
+        contents: StrTendril,
+    },
+    Element {
+        name: QualName,
+        attrs: RefCell<Vec<Attribute>>,
+        template_contents: Option<Handle>,
+    },
+    ProcessingInstruction {
+        target: StrTendril,
+        contents: StrTendril,
+    },
+}
+
+/// A node of the tree
+pub struct Node {
+    pub data: NodeData,
+    pub parent: Cell<Option<Weak<Node>>>,
+    pub children: RefCell<Vec<Handle>>,
+    /// Line where the node starts in the source (1-based)
+    pub line: u64,
+}
+
+impl Node {
+    fn new(data: NodeData, line: u64) -> Handle {
+        Rc::new(Node {
+            data,
+            parent: Cell::new(None),
+            children: RefCell::new(Vec::new()),
+            line,
+        })
+    }
+
+    /// Returns the local name of the element, if this node is an element
+    pub fn name(&self) -> Option<&str> {
+        match &self.data {
+            NodeData::Element { name, .. } => Some(&name.local),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of an attribute of the element, if present
+    pub fn attr(&self, attr: &str) -> Option<String> {
+        match &self.data {
+            NodeData::Element { attrs, .. } => attrs
+                .borrow()
+                .iter()
+                .find(|a| a.name.prefix.is_none() && &*a.name.local == attr)
+                .map(|a| a.value.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the name of the tag following a `<`, in lowercase source
+fn tag_name(lower: &str) -> &str {
+    let end = lower
+        .find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')
+        .unwrap_or(lower.len());
+    &lower[..end]
+}
+
+/// Appends a node without parent to the children of another node
+fn append(parent: &Handle, child: Handle) {
+    let previous = child.parent.replace(Some(Rc::downgrade(parent)));
+    debug_assert!(previous.is_none());
+    parent.children.borrow_mut().push(child);
+}
+
+/// Returns the parent of the node and the index of the node in its children
+fn parent_and_index(target: &Handle) -> Option<(Handle, usize)> {
+    let weak = target.parent.take()?;
+    target.parent.set(Some(weak.clone()));
+    let parent = weak.upgrade()?;
+    let i = parent
+        .children
+        .borrow()
+        .iter()
+        .position(|c| Rc::ptr_eq(c, target))?;
+    Some((parent, i))
+}
+
+fn append_to_text(prev: &Handle, text: &str) -> bool {
+    match &prev.data {
+        NodeData::Text { contents } => {
+            contents.borrow_mut().push_slice(text);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn remove_from_parent(target: &Handle) {
+    if let Some((parent, i)) = parent_and_index(target) {
+        parent.children.borrow_mut().remove(i);
+        target.parent.set(None);
+    }
+}
+
+/// Tree built by html5ever, following the WHATWG tree construction rules
+pub struct Dom {
+    pub document: Handle,
+    /// Quirks mode of the document, set by its doctype
+    pub quirks_mode: Cell<QuirksMode>,
+    line: Cell<u64>,
+}
+
+impl Dom {
+    /// Parses an HTML document, or a fragment of one (e.g. a template partial) if the source has no
+    /// doctype, `html`, `head` or `body` tags, which would otherwise be implied
+    pub fn parse(src: &str) -> Self {
+        let lower = src.to_ascii_lowercase();
+        let tags: Vec<&str> = lower
+            .match_indices('<')
+            .map(|(i, _)| tag_name(&lower[i + 1..]))
+            .collect();
+        if tags
+            .iter()
+            .any(|t| matches!(*t, "!doctype" | "html" | "head" | "body"))
+        {
+            return Self::parse_document(src);
+        }
+        // Table content is only parsed as such inside of a table
+        let first = tags
+            .into_iter()
+            .find(|t| t.starts_with(|c: char| c.is_ascii_alphabetic()));
+        let context = match first {
+            Some("tr") => "tbody",
+            Some("td" | "th") => "tr",
+            Some("tbody" | "thead" | "tfoot" | "caption" | "colgroup" | "col") => "table",
+            _ => "body",
+        };
+        let dom = parse_fragment(
+            Self::new(),
+            ParseOpts::default(),
+            QualName::new(None, ns!(html), LocalName::from(context)),
+            Vec::new(),
+            false,
+        )
+        .one(src);
+        // The fragment is parsed into an html element, which is not part of the source
+        let root = dom.document.children.take();
+        for node in root {
+            for child in node.children.take() {
+                child.parent.set(None);
+                append(&dom.document, child);
+            }
+        }
+        dom
+    }
+
+    /// Parses a whole HTML document
+    pub fn parse_document(src: &str) -> Self {
+        parse_document(Self::new(), ParseOpts::default()).one(src)
+    }
+
+    fn new() -> Self {
+        Dom {
+            document: Node::new(NodeData::Document, 1),
+            quirks_mode: Cell::new(QuirksMode::NoQuirks),
+            line: Cell::new(1),
+        }
+    }
+
+    fn new_node(&self, data: NodeData) -> Handle {
+        Node::new(data, self.line.get())
+    }
+}
+
+impl TreeSink for Dom {
+    type Handle = Handle;
+    type Output = Self;
+    type ElemName<'a> = &'a QualName;
+
+    fn finish(self) -> Self {
+        self
+    }
+
+    // Browsers recover from every parse error, so do we.
+    fn parse_error(&self, _msg: Cow<'static, str>) {}
+
+    fn get_document(&self) -> Handle {
+        self.document.clone()
+    }
+
+    fn elem_name<'a>(&'a self, target: &'a Handle) -> &'a QualName {
+        match &target.data {
+            NodeData::Element { name, .. } => name,
+            _ => panic!("Not an element"),
+        }
+    }
+
+    fn create_element(&self, name: QualName, attrs: Vec<Attribute>, flags: ElementFlags) -> Handle {
+        let template_contents = flags.template.then(|| self.new_node(NodeData::Document));
+        self.new_node(NodeData::Element {
+            name,
+            attrs: RefCell::new(attrs),
+            template_contents,
+        })
+    }
+
+    fn create_comment(&self, text: StrTendril) -> Handle {
+        self.new_node(NodeData::Comment { contents: text })
+    }
+
+    fn create_pi(&self, target: StrTendril, data: StrTendril) -> Handle {
+        self.new_node(NodeData::ProcessingInstruction {
+            target,
+            contents: data,
+        })
+    }
+
+    fn append(&self, parent: &Handle, child: NodeOrText<Handle>) {
+        match child {
+            NodeOrText::AppendText(text) => {
+                if let Some(last) = parent.children.borrow().last()
+                    && append_to_text(last, &text)
+                {
+                    return;
+                }
+                append(
+                    parent,
+                    self.new_node(NodeData::Text {
+                        contents: RefCell::new(text),
+                    }),
+                )
+            }
+            NodeOrText::AppendNode(node) => append(parent, node),
+        }
+    }
+
+    fn append_based_on_parent_node(
+        &self,
+        element: &Handle,
+        prev_element: &Handle,
+        child: NodeOrText<Handle>,
+    ) {
+        let parent = element.parent.take();
+        let has_parent = parent.is_some();
+        element.parent.set(parent);
+        if has_parent {
+            self.append_before_sibling(element, child);
+        } else {
+            self.append(prev_element, child);
+        }
+    }
+
+    fn append_doctype_to_document(
+        &self,
+        name: StrTendril,
+        public_id: StrTendril,
+        system_id: StrTendril,
+    ) {
+        append(
+            &self.document,
+            self.new_node(NodeData::Doctype {
+                name,
+                public_id,
+                system_id,
+            }),
+        );
+    }
+
+    fn get_template_contents(&self, target: &Handle) -> Handle {
+        match &target.data {
+            NodeData::Element {
+                template_contents: Some(contents),
+                ..
+            } => contents.clone(),
+            _ => panic!("Not a template element"),
+        }
+    }
+
+    fn same_node(&self, x: &Handle, y: &Handle) -> bool {
+        Rc::ptr_eq(x, y)
+    }
+
+    fn set_quirks_mode(&self, mode: QuirksMode) {
+        self.quirks_mode.set(mode);
+    }
+
+    fn append_before_sibling(&self, sibling: &Handle, child: NodeOrText<Handle>) {
+        let (parent, i) =
+            parent_and_index(sibling).expect("append_before_sibling called on orphan node");
+        let child = match child {
+            NodeOrText::AppendText(text) => {
+                if i > 0 && append_to_text(&parent.children.borrow()[i - 1], &text) {
+                    return;
+                }
+                self.new_node(NodeData::Text {
+                    contents: RefCell::new(text),
+                })
+            }
+            NodeOrText::AppendNode(node) => {
+                remove_from_parent(&node);
+                node
+            }
+        };
+        child.parent.set(Some(Rc::downgrade(&parent)));
+        parent.children.borrow_mut().insert(i, child);
+    }
+
+    fn add_attrs_if_missing(&self, target: &Handle, attrs: Vec<Attribute>) {
+        if let NodeData::Element {
+            attrs: existing, ..
+        } = &target.data
+        {
+            let mut existing = existing.borrow_mut();
+            let names: HashSet<QualName> = existing.iter().map(|a| a.name.clone()).collect();
+            existing.extend(attrs.into_iter().filter(|a| !names.contains(&a.name)));
+        }
+    }
+
+    fn remove_from_parent(&self, target: &Handle) {
+        remove_from_parent(target);
+    }
+
+    fn reparent_children(&self, node: &Handle, new_parent: &Handle) {
+        let children = std::mem::take(&mut *node.children.borrow_mut());
+        for child in &children {
+            child.parent.set(Some(Rc::downgrade(new_parent)));
+        }
+        new_parent.children.borrow_mut().extend(children);
+    }
+
+    fn set_current_line(&self, line_number: u64) {
+        self.line.set(line_number);
+    }
+}
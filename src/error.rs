@@ -0,0 +1,275 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// Position in a source file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// Line number, starting from 1
+    pub line: usize,
+    /// Column number (in characters), starting from 1
+    pub col: usize,
+    /// Length of the span (in characters), at least 1
+    pub len: usize,
+}
+
+impl Span {
+    /// Gets the position of a byte range in a source
+    pub fn from_range(src: &str, start: usize, end: usize) -> Self {
+        let mut span = Span {
+            line: 1,
+            col: 1,
+            len: 0,
+        };
+        for (i, c) in src.char_indices() {
+            if i >= end || (i >= start && c == '\n') {
+                break;
+            } else if i >= start {
+                span.len += 1;
+            } else if c == '\n' {
+                span.line += 1;
+                span.col = 1;
+            } else {
+                span.col += 1;
+            }
+        }
+        span.len = span.len.max(1);
+        span
+    }
+
+    /// Gets the position of a byte offset in a source
+    pub fn from_offset(src: &str, offset: usize) -> Self {
+        Self::from_range(src, offset, offset)
+    }
+
+    /// Moves a span relative to a source embedded at `base` into the outer source
+    pub fn offset_by(self, base: Span) -> Self {
+        if self.line == 1 {
+            Span {
+                col: base.col + self.col - 1,
+                line: base.line,
+                ..self
+            }
+        } else {
+            Span {
+                line: base.line + self.line - 1,
+                ..self
+            }
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, col {}", self.line, self.col)
+    }
+}
+
+/// Error returned by wwwfmt
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to read or write a file or a directory
+    Io {
+        path: Option<PathBuf>,
+        msg: String,
+        source: io::Error,
+    },
+    /// Failed to find or parse the configuration
+    Config {
+        path: Option<PathBuf>,
+        span: Option<Span>,
+        msg: String,
+    },
+    /// A path can't be formatted or has no valid output path
+    Path { path: PathBuf, msg: String },
+    /// Failed to format HTML
+    Html {
+        path: Option<PathBuf>,
+        span: Option<Span>,
+        msg: String,
+        help: Option<String>,
+    },
+    /// Failed to parse or format CSS
+    Css {
+        path: Option<PathBuf>,
+        span: Option<Span>,
+        msg: String,
+        help: Option<String>,
+    },
+    /// Failed to parse JavaScript (or TypeScript)
+    JavaScript {
+        path: Option<PathBuf>,
+        span: Option<Span>,
+        msg: String,
+        help: Option<String>,
+    },
+    /// Failed to parse JSON
+    Json {
+        path: Option<PathBuf>,
+        span: Option<Span>,
+        msg: String,
+        help: Option<String>,
+    },
+    /// Failed to parse XML (or SVG)
+    Xml {
+        path: Option<PathBuf>,
+        span: Option<Span>,
+        msg: String,
+        help: Option<String>,
+    },
+}
+
+impl Error {
+    /// Creates an IO error, `msg` is prepended to the IO error's message
+    pub fn io<P: Into<PathBuf>>(path: Option<P>, msg: &str, source: io::Error) -> Self {
+        Error::Io {
+            path: path.map(|p| p.into()),
+            msg: format!("{msg}: {source}"),
+            source,
+        }
+    }
+
+    /// Returns the path of the file that caused the error, if known
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::Io { path, .. }
+            | Error::Config { path, .. }
+            | Error::Html { path, .. }
+            | Error::Css { path, .. }
+            | Error::JavaScript { path, .. }
+            | Error::Json { path, .. }
+            | Error::Xml { path, .. } => path.as_deref(),
+            Error::Path { path, .. } => Some(path),
+        }
+    }
+
+    /// Returns the position of the error in the file, if known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Config { span, .. }
+            | Error::Html { span, .. }
+            | Error::Css { span, .. }
+            | Error::JavaScript { span, .. }
+            | Error::Json { span, .. }
+            | Error::Xml { span, .. } => *span,
+            Error::Io { .. } | Error::Path { .. } => None,
+        }
+    }
+
+    /// Returns the error's message, without path and position
+    pub fn msg(&self) -> &str {
+        match self {
+            Error::Io { msg, .. }
+            | Error::Config { msg, .. }
+            | Error::Path { msg, .. }
+            | Error::Html { msg, .. }
+            | Error::Css { msg, .. }
+            | Error::JavaScript { msg, .. }
+            | Error::Json { msg, .. }
+            | Error::Xml { msg, .. } => msg,
+        }
+    }
+
+    /// Returns the parser's help text, if any
+    pub fn help(&self) -> Option<&str> {
+        match self {
+            Error::Html { help, .. }
+            | Error::Css { help, .. }
+            | Error::JavaScript { help, .. }
+            | Error::Json { help, .. }
+            | Error::Xml { help, .. } => help.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Moves the position of an error of a source embedded at `base` into the outer source. Errors
+    /// without a position are placed at `base`.
+    pub fn offset_by(mut self, base: Span) -> Self {
+        match &mut self {
+            Error::Config { span, .. }
+            | Error::Html { span, .. }
+            | Error::Css { span, .. }
+            | Error::JavaScript { span, .. }
+            | Error::Json { span, .. }
+            | Error::Xml { span, .. } => {
+                span.replace(span.map(|s| s.offset_by(base)).unwrap_or(base));
+            }
+            Error::Io { .. } | Error::Path { .. } => {}
+        }
+        self
+    }
+
+    /// Renders the error as a diagnostic showing the offending line of `src` (the content of the
+    /// file that caused the error), with the span underlined.
+    ///
+    /// Falls back to the error's message if the error has no position.
+    pub fn report(&self, src: &str) -> String {
+        let span = match self.span() {
+            Some(span) => span,
+            None => return format!("error: {self}\n"),
+        };
+        let line = src.lines().nth(span.line - 1).unwrap_or_default();
+        let gutter = " ".repeat(span.line.to_string().len());
+        let mut out = format!("error: {}\n", self.msg());
+        out.push_str(&format!("{gutter}--> "));
+        match self.path() {
+            Some(path) => out.push_str(&path.display().to_string()),
+            None => out.push_str("<source>"),
+        }
+        out.push_str(&format!(":{}:{}\n", span.line, span.col));
+        out.push_str(&format!("{gutter} |\n{} | {line}\n{gutter} | ", span.line));
+        // Keeps tabs so the caret stays aligned
+        for c in line.chars().take(span.col - 1) {
+            out.push(if c == '\t' { '\t' } else { ' ' });
+        }
+        out.push_str(&"^".repeat(span.len));
+        out.push('\n');
+        if let Some(help) = self.help() {
+            out.push_str(&format!("{gutter} = help: {help}\n"));
+        }
+        out
+    }
+
+    /// Sets the path of the file that caused the error, if not already set
+    pub fn with_path<P: AsRef<Path>>(mut self, new: P) -> Self {
+        match &mut self {
+            Error::Io { path, .. }
+            | Error::Config { path, .. }
+            | Error::Html { path, .. }
+            | Error::Css { path, .. }
+            | Error::JavaScript { path, .. }
+            | Error::Json { path, .. }
+            | Error::Xml { path, .. } => {
+                if path.is_none() {
+                    path.replace(new.as_ref().to_path_buf());
+                }
+            }
+            Error::Path { .. } => {}
+        }
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = self.path() {
+            write!(f, "{}: ", path.display())?;
+        }
+        if let Some(span) = self.span() {
+            write!(f, "At {span}: ")?;
+        }
+        write!(f, "{}", self.msg())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
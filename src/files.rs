@@ -4,18 +4,76 @@ use std::{
     fs::{self, File},
     io::{self, Read, Write},
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use oxc::allocator::Allocator;
+use ignore::{
+    WalkBuilder,
+    gitignore::{Gitignore, GitignoreBuilder},
+};
+use similar::TextDiff;
 
-use crate::{config::Config, fmt};
+use crate::{
+    cache::CACHE_FILE,
+    config::Config,
+    error::{Error, Result},
+};
 
-fn should_ignore(path: &PathBuf, config: &Config) -> bool {
-    if let Some(path) = path.to_str() {
-        config.ignore_path.contains(&path.to_owned())
-    } else {
-        false
+/// Ignore and include patterns of the configuration. Patterns follow the gitignore syntax and are
+/// relative to the project's root.
+pub struct Filter {
+    root: PathBuf,
+    ignore: Gitignore,
+    include: Gitignore,
+}
+
+impl Filter {
+    /// Compiles the patterns of the configuration
+    pub fn new(root: &Path, config: &Config) -> Result<Self> {
+        Ok(Self {
+            root: root.to_path_buf(),
+            ignore: patterns(root, &config.ignore_path, "ignore_path")?,
+            include: patterns(root, &config.include_path, "include_path")?,
+        })
+    }
+
+    /// Returns whether a directory or a file is ignored, or is inside an ignored directory
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        path.starts_with(&self.root)
+            && self
+                .ignore
+                .matched_path_or_any_parents(path, is_dir)
+                .is_ignore()
+    }
+
+    /// Returns whether a file should be formatted: it must not be ignored and, if any include
+    /// pattern is specified, it must match one of them. Files outside the project are never
+    /// filtered.
+    pub fn allows(&self, path: &Path) -> bool {
+        if !path.starts_with(&self.root) {
+            return true;
+        }
+        !self.is_ignored(path, false)
+            && (self.include.is_empty()
+                || self
+                    .include
+                    .matched_path_or_any_parents(path, false)
+                    .is_ignore())
+    }
+}
+
+/// Compiles a list of gitignore-style patterns
+fn patterns(root: &Path, globs: &[String], key: &str) -> Result<Gitignore> {
+    let err = |e: ignore::Error| Error::Config {
+        path: None,
+        span: None,
+        msg: format!("Invalid pattern in {key}: {e}"),
+    };
+    let mut builder = GitignoreBuilder::new(root);
+    for glob in globs {
+        builder.add_line(None, glob).map_err(err)?;
     }
+    builder.build().map_err(err)
 }
 
 #[inline]
@@ -25,30 +83,41 @@ pub fn get_extension(path: &Path) -> Option<String> {
         .map(|x| x.to_lowercase())
 }
 
-pub fn recurse_dir(
-    path: &PathBuf,
-    root: &Option<PathBuf>,
-    config: &Config,
-    minify: bool,
-    inplace: bool,
-    alloc: &Allocator,
-) -> Result<(), String> {
-    for entry in fs::read_dir(&path)
-        .map_err(|e| format!("Failed to read directory {}: {e}", path.display()))?
-    {
-        match entry {
-            Ok(f) if f.path().is_file() && !should_ignore(&f.path(), config) => {
-                let ext = match get_extension(&f.path()) {
-                    Some(e) => e,
-                    None => continue,
-                };
-                fmt::inner_file(&f.path(), root, ext, config, minify, inplace, alloc)
-                    .map_err(|e| format!("{}: {e}", f.path().display()))?;
-            }
-            Ok(d) if d.path().is_dir() && !should_ignore(&d.path(), config) => {
-                recurse_dir(&d.path(), root, config, minify, inplace, alloc)?
+/// Collects recursively every file that can be formatted, starting from the project's root.
+///
+/// Unless disabled in the configuration, `.gitignore`, `.ignore` and `.wwwfmtignore` files are
+/// honored at any level of the tree, like ripgrep does.
+pub fn recurse_dir(root: &Path, config: &Config, files: &mut Vec<PathBuf>) -> Result<()> {
+    let filter = Arc::new(Filter::new(root, config)?);
+    let mut walker = WalkBuilder::new(root);
+    walker
+        .standard_filters(config.respect_ignore_files)
+        .hidden(false)
+        .require_git(false)
+        .follow_links(true)
+        .sort_by_file_name(|a, b| a.cmp(b));
+    if config.respect_ignore_files {
+        walker.add_custom_ignore_filename(".wwwfmtignore");
+    }
+    let skip_git = config.respect_ignore_files;
+    let walker = walker
+        .filter_entry(move |e| {
+            if e.depth() == 0 {
+                true
+            } else if e.depth() == 1 && e.file_name() == CACHE_FILE {
+                false
+            } else if e.file_type().is_some_and(|t| t.is_dir()) {
+                !(skip_git && e.file_name() == ".git" || filter.is_ignored(e.path(), true))
+            } else {
+                filter.allows(e.path())
             }
-            _ => continue,
+        })
+        .build();
+    for entry in walker {
+        let entry = entry
+            .map_err(|e| Error::io(Some(root), "Failed to read directory", io::Error::other(e)))?;
+        if entry.file_type().is_some_and(|t| t.is_file()) && get_extension(entry.path()).is_some() {
+            files.push(entry.into_path());
         }
     }
     Ok(())
@@ -57,10 +126,7 @@ pub fn recurse_dir(
 /// Reverse traversal to find a certain file from a filename
 pub fn revtraverse(path: PathBuf, find: &str) -> io::Result<PathBuf> {
     if !path.is_dir() || !path.has_root() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Path must be a full directory.".to_string(),
-        ));
+        return Err(io::Error::other("Path must be a full directory."));
     }
     let mut curpath = Some(path);
     while let Some(path) = curpath {
@@ -80,8 +146,25 @@ pub fn revtraverse(path: PathBuf, find: &str) -> io::Result<PathBuf> {
 
 /// Gets current directory (shorthand)
 #[inline(always)]
-pub fn get_currdir() -> Result<PathBuf, String> {
-    env::current_dir().map_err(|e| format!("Failed to get current working directory: {e}"))
+pub fn get_currdir() -> Result<PathBuf> {
+    env::current_dir().map_err(|e| {
+        Error::io(
+            None::<PathBuf>,
+            "Failed to get current working directory",
+            e,
+        )
+    })
+}
+
+/// Returns the extension of the JavaScript a TypeScript or JSX file is transpiled to, or None if
+/// the extension is not transpiled
+pub fn transpiled_extension(ext: &str) -> Option<&'static str> {
+    match ext {
+        "ts" | "tsx" | "jsx" => Some("js"),
+        "mts" => Some("mjs"),
+        "cts" => Some("cjs"),
+        _ => None,
+    }
 }
 
 /// Returns new path for the formatted file
@@ -90,9 +173,16 @@ pub fn outdir(
     config: &Config,
     root: &Option<PathBuf>,
     minify: bool,
-) -> Result<PathBuf, String> {
+) -> Result<PathBuf> {
+    if minify
+        && config.javascript.uglify_transpile
+        && let Some(ext) = get_extension(&file_path)
+        && let Some(ext) = transpiled_extension(&ext)
+    {
+        file_path.set_extension(ext);
+    }
     if let Some(root) = root {
-        if file_path.starts_with(&root) {
+        if file_path.starts_with(root) {
             let root_path: Vec<&OsStr> = root.iter().collect();
             let mut file_path: Vec<&OsStr> = file_path.iter().collect();
             let outdir = if minify {
@@ -100,7 +190,10 @@ pub fn outdir(
             } else {
                 config.prettify_outdir.clone()
             }
-            .ok_or("No outdir specified")?;
+            .ok_or_else(|| Error::Path {
+                path: file_path.iter().collect(),
+                msg: "No outdir specified".into(),
+            })?;
             file_path.insert(root_path.len(), OsStr::new(&outdir));
 
             let mut new_filepath = PathBuf::new();
@@ -109,16 +202,19 @@ pub fn outdir(
             }
             Ok(new_filepath)
         } else {
-            Err(format!(
-                "{} not in project's directory",
-                file_path.display()
-            ))
+            Err(Error::Path {
+                path: file_path,
+                msg: "Not in project's directory".into(),
+            })
         }
     } else {
         let ext = file_path
             .extension()
             .and_then(|x| x.to_str())
-            .ok_or("Invalid file extension")?;
+            .ok_or_else(|| Error::Path {
+                path: file_path.clone(),
+                msg: "Invalid file extension".into(),
+            })?;
         if minify {
             file_path.set_extension(format!("min.{ext}"));
         } else {
@@ -128,28 +224,68 @@ pub fn outdir(
     }
 }
 
+/// Returns the path of `to` relative to the directory `from`
+pub fn relative(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<_> = from.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for c in &to[common..] {
+        path.push(c);
+    }
+    path
+}
+
+/// Returns the path of the source map of a file (file.ext.map)
+pub fn map_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".map");
+    PathBuf::from(path)
+}
+
 /// Opens file and reads its content
-pub fn read(path: &Path) -> Result<String, String> {
+pub fn read(path: &Path) -> Result<String> {
     let mut buf = String::new();
     File::open(path)
-        .map_err(|e| format!("Failed to open file to format: {e}"))?
+        .map_err(|e| Error::io(Some(path), "Failed to open file to format", e))?
         .read_to_string(&mut buf)
-        .map_err(|e| format!("Failed to read file to format: {e}"))?;
+        .map_err(|e| Error::io(Some(path), "Failed to read file to format", e))?;
     Ok(buf)
 }
 
 /// Writes data to file, in the right mode and in the right directory.
 /// Creates the output directory if it doesn't exist.
-pub fn write(path: &PathBuf, out_path: Option<PathBuf>, data: &[u8]) -> Result<(), String> {
-    let mut file = if let Some(mut out_path) = out_path {
+pub fn write(path: &Path, out_path: Option<PathBuf>, data: &[u8]) -> Result<()> {
+    let out_file = if let Some(mut out_path) = out_path {
         let out_file = out_path.clone();
         out_path.pop();
-        fs::create_dir_all(&out_path).map_err(|e| format!("Failed to create dir: {e}"))?;
-        File::create(out_file).map_err(|e| format!("Failed to create file: {e}"))?
+        fs::create_dir_all(&out_path)
+            .map_err(|e| Error::io(Some(&out_path), "Failed to create dir", e))?;
+        out_file
     } else {
-        File::create(path).map_err(|e| format!("Failed to open file to format: {e}"))?
+        path.to_path_buf()
     };
-    file.write_all(data)
-        .map_err(|e| format!("Failed to write to file: {e}"))?;
+    File::create(&out_file)
+        .map_err(|e| Error::io(Some(&out_file), "Failed to create file", e))?
+        .write_all(data)
+        .map_err(|e| Error::io(Some(&out_file), "Failed to write to file", e))?;
     Ok(())
 }
+
+/// Returns whether the file at `path` has a different content from `data`.
+/// Files that can't be read are considered different.
+pub fn differs(path: &Path, data: &[u8]) -> bool {
+    fs::read(path).map(|old| old != data).unwrap_or(true)
+}
+
+/// Returns the unified diff between the original and the formatted content of a file
+pub fn diff(path: &Path, old: &str, new: &str) -> String {
+    let name = path.display().to_string();
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(&name, &name)
+        .to_string()
+}
@@ -1,38 +1,375 @@
-use std::path::PathBuf;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
 
-use oxc::allocator::Allocator;
+use base64::prelude::{BASE64_STANDARD, Engine};
+use oxc::{allocator::Allocator, span::SourceType};
 
-use crate::{config::Config, css, files, html, javascript};
+use crate::{
+    cache::Cache,
+    config::{Config, SourceMap},
+    css,
+    error::{Error, Result},
+    files,
+    html::Html,
+    javascript, json, xml,
+};
 
-/// Formats one file. Used internally.
+/// Whether to minify or prettify
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Minify,
+    Prettify,
+}
+
+/// Language of the source to format
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Language {
+    Html,
+    Css,
+    /// JavaScript module (.js, .mjs)
+    JavaScript,
+    /// CommonJS script (.cjs)
+    CommonJs,
+    /// JavaScript with JSX (.jsx)
+    Jsx,
+    /// TypeScript module (.ts, .mts)
+    TypeScript,
+    /// CommonJS TypeScript script (.cts)
+    CommonTypeScript,
+    /// TypeScript with JSX (.tsx)
+    Tsx,
+    /// JSON (.json, .webmanifest, .map)
+    Json,
+    /// JSON with comments (.jsonc)
+    Jsonc,
+    /// SVG image (.svg)
+    Svg,
+    /// XML (.xml, .rss, .atom)
+    Xml,
+}
+
+impl Language {
+    /// Recognizes the language from a (lowercase) file extension
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "html" | "htm" => Some(Self::Html),
+            "css" => Some(Self::Css),
+            "js" | "mjs" => Some(Self::JavaScript),
+            "cjs" => Some(Self::CommonJs),
+            "jsx" => Some(Self::Jsx),
+            "ts" | "mts" => Some(Self::TypeScript),
+            "cts" => Some(Self::CommonTypeScript),
+            "tsx" => Some(Self::Tsx),
+            "json" | "webmanifest" | "map" => Some(Self::Json),
+            "jsonc" => Some(Self::Jsonc),
+            "svg" => Some(Self::Svg),
+            "xml" | "rss" | "atom" => Some(Self::Xml),
+            _ => None,
+        }
+    }
+
+    /// Returns the Oxc source type of JavaScript and TypeScript languages
+    pub fn source_type(&self) -> Option<SourceType> {
+        let ext = match self {
+            Self::Html | Self::Css | Self::Json | Self::Jsonc | Self::Svg | Self::Xml => {
+                return None;
+            }
+            Self::JavaScript => "js",
+            Self::CommonJs => "cjs",
+            Self::Jsx => "jsx",
+            Self::TypeScript => "ts",
+            Self::CommonTypeScript => "cts",
+            Self::Tsx => "tsx",
+        };
+        SourceType::from_extension(ext).ok()
+    }
+}
+
+/// Formats a string in memory. Used internally.
+pub fn inner_fmt_str(
+    src: &str,
+    lang: Language,
+    config: &Config,
+    minify: bool,
+    alloc: &Allocator,
+) -> Result<String> {
+    match lang {
+        Language::Html => {
+            let html = Html::new(src, alloc, config);
+            let fmted = if minify {
+                html.minify()?
+            } else {
+                html.prettify()?
+            };
+            Ok(String::from_utf8(fmted).expect("Formatted HTML is not UTF-8"))
+        }
+        Language::Css => css::fmt_str(src, config, minify),
+        Language::Json => json::fmt_str(src, config, minify, false),
+        Language::Jsonc => json::fmt_str(src, config, minify, true),
+        Language::Svg => xml::fmt_str(src, config, minify, true),
+        Language::Xml => xml::fmt_str(src, config, minify, false),
+        _ => javascript::fmt_str(
+            src,
+            lang.source_type().expect("Not a JavaScript language"),
+            alloc,
+            config,
+            minify,
+        ),
+    }
+}
+
+/// Gets the (lowercase) extension of a file
+fn extension(path: &Path) -> Result<String> {
+    files::get_extension(path).ok_or_else(|| Error::Path {
+        path: path.to_path_buf(),
+        msg: "Failed to get file extension.".into(),
+    })
+}
+
+/// Formats one file in memory and returns the formatted content, or None if the extension is not
+/// recognized. Used internally.
+pub fn inner_fmt(
+    path: &Path,
+    ext: &str,
+    config: &Config,
+    minify: bool,
+    alloc: &Allocator,
+) -> Result<Option<Vec<u8>>> {
+    let lang = match Language::from_extension(ext) {
+        Some(lang) => lang,
+        None => return Ok(None),
+    };
+    let src = files::read(path)?;
+    inner_fmt_str(&src, lang, config, minify, alloc)
+        .map(|s| Some(s.into_bytes()))
+        .map_err(|e| e.with_path(path))
+}
+
+/// Formats a string in memory and returns the formatted string.
+///
+/// - `src`: the source to format.
+/// - `lang`: the source's language (see [`Language::from_extension`] to get it from a file
+///   extension).
+/// - `config`: Configuration
+/// - `mode`: Whether to minify or prettify
+///
+/// This behaves exactly as formatting a file with the same language, without touching the
+/// filesystem. An [`Error`] is returned in case of error.
+pub fn format_str(src: &str, lang: Language, config: &Config, mode: Mode) -> Result<String> {
+    inner_fmt_str(src, lang, config, mode == Mode::Minify, &Allocator::new())
+}
+
+/// Minifies one file that will be written at `out_path`, generating its source map if enabled in
+/// the configuration. Returns the content and the source map to write next to it (None if it's
+/// inlined), or None if the file has no source map.
+fn fmt_with_map(
+    path: &Path,
+    lang: Language,
+    out_path: &Path,
+    config: &Config,
+    alloc: &Allocator,
+) -> Result<Option<(Vec<u8>, Option<String>)>> {
+    let kind = match lang {
+        Language::Html | Language::Json | Language::Jsonc | Language::Svg | Language::Xml => {
+            SourceMap::None
+        }
+        Language::Css => config.css.uglify_source_map,
+        _ => config.javascript.uglify_source_map,
+    };
+    if kind == SourceMap::None {
+        return Ok(None);
+    }
+    let src = files::read(path)?;
+    let source = files::relative(out_path.parent().unwrap_or(Path::new("")), path);
+    let (mut code, map) = match lang {
+        Language::Css => css::minify_with_map(&src, config, &source.to_string_lossy()),
+        _ => javascript::minify_with_map(
+            &src,
+            lang.source_type().expect("Not a JavaScript language"),
+            alloc,
+            config,
+            &source,
+        ),
+    }
+    .map_err(|e| e.with_path(path))?;
+    let url = match kind {
+        SourceMap::Linked => files::map_path(out_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned()),
+        SourceMap::Inline => Some(format!(
+            "data:application/json;base64,{}",
+            BASE64_STANDARD.encode(&map)
+        )),
+        _ => None,
+    };
+    if let Some(url) = url {
+        code.push_str(&match lang {
+            Language::Css => format!("\n/*# sourceMappingURL={url} */"),
+            _ => format!("\n//# sourceMappingURL={url}"),
+        });
+    }
+    Ok(Some((
+        code.into_bytes(),
+        (kind != SourceMap::Inline).then_some(map),
+    )))
+}
+
+/// Fails if a file would be transpiled in place, since its JavaScript would be written in a
+/// TypeScript or JSX file
+fn transpiled_inplace(path: &Path, ext: &str, config: &Config, minify: bool) -> Result<()> {
+    if minify && config.javascript.uglify_transpile && files::transpiled_extension(ext).is_some() {
+        return Err(Error::Path {
+            path: path.to_path_buf(),
+            msg: "Transpiled files can't be written in place".into(),
+        });
+    }
+    Ok(())
+}
+
+/// Formats one file that will be written at `out_path`, returns the content and the source map
+/// to write next to it if any, or None if the extension is not recognized. Used internally.
+pub fn inner_fmt_to(
+    path: &Path,
+    ext: &str,
+    out_path: &Path,
+    config: &Config,
+    minify: bool,
+    alloc: &Allocator,
+) -> Result<Option<(Vec<u8>, Option<String>)>> {
+    if minify
+        && let Some(lang) = Language::from_extension(ext)
+        && let Some(fmted) = fmt_with_map(path, lang, out_path, config, alloc)?
+    {
+        return Ok(Some(fmted));
+    }
+    Ok(inner_fmt(path, ext, config, minify, alloc)?.map(|fmted| (fmted, None)))
+}
+
+/// Formats one file and returns the formatted content, or None if the extension is not
+/// recognized. Used internally.
 pub fn inner_file(
-    path: &PathBuf,
+    path: &Path,
     root: &Option<PathBuf>,
     ext: String,
     config: &Config,
     minify: bool,
     inplace: bool,
     alloc: &Allocator,
-) -> Result<bool, String> {
+) -> Result<Option<Vec<u8>>> {
     let out_path = if inplace {
+        transpiled_inplace(path, &ext, config, minify)?;
         None
     } else {
-        Some(files::outdir(path.clone(), config, root, minify)?)
+        Some(files::outdir(path.to_path_buf(), config, root, minify)?)
     };
-    match ext.as_str() {
-        "html" | "htm" => {
-            html::fmt(path, out_path, config, minify, alloc)?;
-            Ok(true)
-        }
-        "css" => {
-            css::fmt(path, out_path, config, minify)?;
-            Ok(true)
+    let dest = out_path.clone().unwrap_or_else(|| path.to_path_buf());
+    match inner_fmt_to(path, &ext, &dest, config, minify, alloc)? {
+        Some((fmted, map)) => {
+            files::write(path, out_path, &fmted)?;
+            if let Some(map) = map {
+                files::write(&files::map_path(&dest), None, map.as_bytes())?;
+            }
+            Ok(Some(fmted))
         }
-        "js" | "mjs" | "jsx" | "cjs" | "ts" | "mts" | "cts" | "tsx" => {
-            javascript::fmt(path, out_path, config, minify, alloc)?;
-            Ok(true)
+        None => Ok(None),
+    }
+}
+
+/// Returns the extension of a file of the project if it's formatted, skipping the source maps
+/// written next to the file they map as they are generated. Used internally.
+pub fn project_ext(path: &Path) -> Option<String> {
+    let ext = files::get_extension(path)?;
+    Language::from_extension(&ext)?;
+    (ext != "map" || !path.with_extension("").exists()).then_some(ext)
+}
+
+/// What happened to a file of the project
+enum Outcome {
+    Formatted,
+    Unchanged,
+    Skipped,
+}
+
+/// Formats one file of the project, unless the cache says it didn't change since the last run
+fn project_file(
+    path: &Path,
+    root: &Option<PathBuf>,
+    config: &Config,
+    minify: bool,
+    inplace: bool,
+    alloc: &Allocator,
+    cache: Option<&Cache>,
+) -> Result<Outcome> {
+    let Some(ext) = project_ext(path) else {
+        return Ok(Outcome::Skipped);
+    };
+    let Some(cache) = cache else {
+        inner_file(path, root, ext, config, minify, inplace, alloc)?;
+        return Ok(Outcome::Formatted);
+    };
+    let src =
+        fs::read(path).map_err(|e| Error::io(Some(path), "Failed to read file to format", e))?;
+    let has_output = inplace
+        || files::outdir(path.to_path_buf(), config, root, minify).is_ok_and(|p| p.exists());
+    if has_output && cache.is_fresh(path, &src) {
+        return Ok(Outcome::Unchanged);
+    }
+    if let Some(fmted) = inner_file(path, root, ext, config, minify, inplace, alloc)? {
+        cache.insert(path, if inplace { &fmted } else { &src });
+    }
+    Ok(Outcome::Formatted)
+}
+
+/// Checks if one file would change when formatted. Used internally.
+pub fn inner_check(
+    path: &Path,
+    root: &Option<PathBuf>,
+    ext: String,
+    config: &Config,
+    minify: bool,
+    inplace: bool,
+    alloc: &Allocator,
+) -> Result<bool> {
+    let out_path = if inplace {
+        transpiled_inplace(path, &ext, config, minify)?;
+        path.to_path_buf()
+    } else {
+        files::outdir(path.to_path_buf(), config, root, minify)?
+    };
+    match inner_fmt_to(path, &ext, &out_path, config, minify, alloc)? {
+        Some((fmted, _)) => Ok(files::differs(&out_path, &fmted)),
+        None => Ok(false),
+    }
+}
+
+/// Returns the diff between one file and its content formatted in place (with the link to its
+/// source map, if any), or None if they are the same or the extension is not recognized. Used
+/// internally.
+pub fn inner_diff(
+    path: &Path,
+    ext: String,
+    config: &Config,
+    minify: bool,
+    alloc: &Allocator,
+) -> Result<Option<String>> {
+    match inner_fmt_to(path, &ext, path, config, minify, alloc)? {
+        Some((fmted, _)) => {
+            let orig = files::read(path)?;
+            let fmted = String::from_utf8(fmted).expect("Formatted output is not UTF-8");
+            if orig == fmted {
+                Ok(None)
+            } else {
+                Ok(Some(files::diff(path, &orig, &fmted)))
+            }
         }
-        _ => Ok(false),
+        None => Ok(None),
     }
 }
 
@@ -43,14 +380,14 @@ pub fn inner_file(
 /// - `config`: Configuration
 /// - `minify`: Whether to minify or prettify
 /// - `inplace`: Whether to format in-place or in another file. If the output directory is
-/// specified in the config directory, the output file will end up there, otherwise a new file
-/// called either file.prty.ext or file.min.ext will be created on the same directory.
+///   specified in the config directory, the output file will end up there, otherwise a new file
+///   called either file.prty.ext or file.min.ext will be created on the same directory.
 /// - `alloc`: Oxc's arena [`Allocator`]. You can optionally specify this to improve performance
-/// if you are manually iterating files. (using [`all`] is recommended)
+///   if you are manually iterating files. (using [`all`] is recommended)
 ///
 /// The file's type is automatically recognized by its extension. If the file is recognized
 /// it will successfully write the file and return Ok(true), if the file extension is not recognized
-/// it returns Ok(false). An error string is returned in case of error.
+/// it returns Ok(false). An [`Error`] is returned in case of error.
 pub fn file<P: Into<PathBuf>>(
     path: P,
     root: Option<P>,
@@ -58,13 +395,9 @@ pub fn file<P: Into<PathBuf>>(
     minify: bool,
     inplace: bool,
     alloc: Option<&Allocator>,
-) -> Result<bool, String> {
+) -> Result<bool> {
     let path: PathBuf = path.into();
-    let ext = path
-        .extension()
-        .and_then(|x| x.to_str())
-        .map(|x| x.to_lowercase())
-        .ok_or("Failed to get file extension.")?;
+    let ext = extension(&path)?;
     let alloc = if let Some(alloc) = alloc {
         alloc
     } else {
@@ -79,19 +412,214 @@ pub fn file<P: Into<PathBuf>>(
         inplace,
         alloc,
     )
+    .map(|fmted| fmted.is_some())
+}
+
+/// Checks whether one file is already formatted, without writing anything.
+///
+/// Takes the same arguments as [`file()`]. The formatted content is compared with the content of the
+/// file it would be written to (the file itself if `inplace`, otherwise the output file). Returns
+/// Ok(true) if the file would change, Ok(false) if it would not or if the file extension is not
+/// recognized.
+pub fn check_file<P: Into<PathBuf>>(
+    path: P,
+    root: Option<P>,
+    config: &Config,
+    minify: bool,
+    inplace: bool,
+    alloc: Option<&Allocator>,
+) -> Result<bool> {
+    let path: PathBuf = path.into();
+    let ext = extension(&path)?;
+    let alloc = if let Some(alloc) = alloc {
+        alloc
+    } else {
+        &Allocator::new()
+    };
+    inner_check(
+        &path,
+        &root.map(|p| p.into()),
+        ext,
+        config,
+        minify,
+        inplace,
+        alloc,
+    )
+}
+
+/// Returns the unified diff between one file and its content formatted in place, without writing
+/// anything.
+///
+/// Takes the same arguments as [`file()`], except for the output options. Returns Ok(None) if the
+/// file would not change or if the file extension is not recognized.
+pub fn diff_file<P: Into<PathBuf>>(
+    path: P,
+    config: &Config,
+    minify: bool,
+    alloc: Option<&Allocator>,
+) -> Result<Option<String>> {
+    let path: PathBuf = path.into();
+    let ext = extension(&path)?;
+    let alloc = if let Some(alloc) = alloc {
+        alloc
+    } else {
+        &Allocator::new()
+    };
+    inner_diff(&path, ext, config, minify, alloc)
+}
+
+/// Outcome of formatting a project's files with [`all`]
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Files that were formatted
+    pub formatted: Vec<PathBuf>,
+    /// Files that were not formatted because they didn't change since the last run
+    pub unchanged: Vec<PathBuf>,
+    /// Files that were skipped because their extension is not recognized
+    pub skipped: Vec<PathBuf>,
+    /// Files that failed to format, with the reason
+    pub failed: Vec<(PathBuf, Error)>,
+}
+
+impl Report {
+    /// Returns true if no file failed to format
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Sorts the files by path, threads finish in any order
+    fn sort(&mut self) {
+        self.formatted.sort();
+        self.unchanged.sort();
+        self.skipped.sort();
+        self.failed.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
 }
 
 /// Formats all files starting from the project's root directory.
 ///
 /// The file's type are automatically recognized by their extension, if an extension is not
 /// recognized the file is skipped.
+///
+/// Files are formatted on `jobs` worker threads, each with its own [`Allocator`]. If `jobs` is 0
+/// the number of available cores is used.
+///
+/// If `cache` is true, the files that didn't change since the last run (with the same
+/// configuration and mode) are not formatted again. The hashes of the files are stored in a
+/// `.wwwfmt.cache` file in the project's root directory.
+///
+/// A file that fails to format does not stop the others: every file is processed and the outcome
+/// of each one is collected in the returned [`Report`]. An [`Error`] is returned only if the
+/// project's files can't be listed.
 pub fn all<P: Into<PathBuf>>(
     root: P,
     config: &Config,
     minify: bool,
     inplace: bool,
-) -> Result<(), String> {
-    let alloc = Allocator::new();
+    jobs: usize,
+    cache: bool,
+) -> Result<Report> {
+    let root: PathBuf = root.into();
+    let mut paths = Vec::new();
+    files::recurse_dir(&root, config, &mut paths)?;
+    let cache = cache.then(|| Cache::open(&root, config, minify, inplace));
+    let root = Some(root);
+    let jobs = match jobs {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+    .min(paths.len())
+    .max(1);
+    let next = AtomicUsize::new(0);
+    let report = Mutex::new(Report::default());
+    thread::scope(|s| {
+        for _ in 0..jobs {
+            s.spawn(|| {
+                let mut alloc = Allocator::new();
+                while let Some(path) = paths.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let res =
+                        project_file(path, &root, config, minify, inplace, &alloc, cache.as_ref());
+                    alloc.reset();
+                    let mut report = report.lock().expect("Report poisoned");
+                    match res {
+                        Ok(Outcome::Formatted) => report.formatted.push(path.clone()),
+                        Ok(Outcome::Unchanged) => report.unchanged.push(path.clone()),
+                        Ok(Outcome::Skipped) => report.skipped.push(path.clone()),
+                        Err(e) => report.failed.push((path.clone(), e)),
+                    }
+                }
+            });
+        }
+    });
+    let mut report = report.into_inner().expect("Report poisoned");
+    if let Some(cache) = cache
+        && let Err(e) = cache.save()
+    {
+        report
+            .failed
+            .push((e.path().expect("Cache error without path").to_path_buf(), e));
+    }
+    report.sort();
+    Ok(report)
+}
+
+/// Checks all files starting from the project's root directory, without writing anything.
+///
+/// Returns a [`Report`] where the files that would change if formatted with [`all`] are
+/// `formatted`, and the ones that are already formatted are `unchanged`.
+pub fn check<P: Into<PathBuf>>(
+    root: P,
+    config: &Config,
+    minify: bool,
+    inplace: bool,
+) -> Result<Report> {
+    let mut alloc = Allocator::new();
     let root: PathBuf = root.into();
-    files::recurse_dir(&root, &Some(root.clone()), config, minify, inplace, &alloc)
+    let mut paths = Vec::new();
+    files::recurse_dir(&root, config, &mut paths)?;
+    let root = Some(root);
+    let mut report = Report::default();
+    for path in paths {
+        let Some(ext) = project_ext(&path) else {
+            report.skipped.push(path);
+            continue;
+        };
+        match inner_check(&path, &root, ext, config, minify, inplace, &alloc) {
+            Ok(true) => report.formatted.push(path),
+            Ok(false) => report.unchanged.push(path),
+            Err(e) => report.failed.push((path, e)),
+        }
+        alloc.reset();
+    }
+    report.sort();
+    Ok(report)
+}
+
+/// Diffs all files starting from the project's root directory, without writing anything.
+///
+/// Returns the path and the unified diff of every file that would change if formatted, or the
+/// error it failed with.
+pub fn diff<P: Into<PathBuf>>(
+    root: P,
+    config: &Config,
+    minify: bool,
+) -> Result<Vec<(PathBuf, Result<String>)>> {
+    let mut alloc = Allocator::new();
+    let root: PathBuf = root.into();
+    let mut paths = Vec::new();
+    files::recurse_dir(&root, config, &mut paths)?;
+    paths.sort();
+    let mut diffs = Vec::new();
+    for path in paths {
+        let Some(ext) = project_ext(&path) else {
+            continue;
+        };
+        match inner_diff(&path, ext, config, minify, &alloc) {
+            Ok(Some(diff)) => diffs.push((path, Ok(diff))),
+            Ok(None) => {}
+            Err(e) => diffs.push((path, Err(e))),
+        }
+        alloc.reset();
+    }
+    Ok(diffs)
 }
@@ -1,58 +1,392 @@
-use std::{
-    io::{BufRead, Cursor, Read, Write},
-    path::PathBuf,
-};
+use std::{borrow::Cow, collections::HashSet, mem};
 
+use html5ever::{ns, tree_builder::QuirksMode};
 use oxc::{allocator::Allocator, span::SourceType};
-use quick_xml::{
-    events::{attributes::Attribute, BytesEnd, BytesStart, Event},
-    Reader, Writer,
+
+use crate::{
+    config::{Config, OmitTags, Whitespace},
+    css,
+    dom::{Dom, Handle, NodeData},
+    error::{Result, Span},
+    javascript, json,
 };
 
-use crate::{config::Config, css, files, javascript};
+/// Elements that have no end tag
+const EMPTY_TAGS: [&str; 18] = [
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
 
-const EMPTY_TAGS: [&[u8]; 14] = [
-    b"area", b"base", b"br", b"col", b"embed", b"hr", b"img", b"input", b"link", b"meta", b"param",
-    b"source", b"track", b"wbr",
+/// Elements whose text content is not escaped
+const RAW_TEXT_TAGS: [&str; 8] = [
+    "style",
+    "script",
+    "xmp",
+    "iframe",
+    "noembed",
+    "noframes",
+    "plaintext",
+    "noscript",
 ];
 
-fn position(src: &str, pos: u64) -> String {
-    let src = match src.get(0..(pos as usize)) {
-        Some(s) => s,
-        None => return "unknown position".into(),
-    };
-    let mut lines = 0;
-    let mut cols = 0;
-    for c in src.chars() {
-        match c {
-            '\n' => {
-                cols = 0;
-                lines += 1;
-            }
-            _ => cols += 1,
-        }
+/// Attributes whose presence is their value (element, attribute), `*` standing for every HTML
+/// element
+const BOOLEAN_ATTRS: [(&str, &str); 43] = [
+    ("*", "autofocus"),
+    ("*", "hidden"),
+    ("*", "inert"),
+    ("*", "itemscope"),
+    ("audio", "autoplay"),
+    ("audio", "controls"),
+    ("audio", "loop"),
+    ("audio", "muted"),
+    ("button", "disabled"),
+    ("button", "formnovalidate"),
+    ("details", "open"),
+    ("dialog", "open"),
+    ("fieldset", "disabled"),
+    ("form", "novalidate"),
+    ("iframe", "allowfullscreen"),
+    ("img", "ismap"),
+    ("input", "checked"),
+    ("input", "disabled"),
+    ("input", "formnovalidate"),
+    ("input", "multiple"),
+    ("input", "readonly"),
+    ("input", "required"),
+    ("link", "disabled"),
+    ("ol", "reversed"),
+    ("optgroup", "disabled"),
+    ("option", "disabled"),
+    ("option", "selected"),
+    ("script", "async"),
+    ("script", "defer"),
+    ("script", "nomodule"),
+    ("select", "disabled"),
+    ("select", "multiple"),
+    ("select", "required"),
+    ("template", "shadowrootclonable"),
+    ("textarea", "disabled"),
+    ("textarea", "readonly"),
+    ("textarea", "required"),
+    ("track", "default"),
+    ("video", "autoplay"),
+    ("video", "controls"),
+    ("video", "loop"),
+    ("video", "muted"),
+    ("video", "playsinline"),
+];
+
+/// Attribute values (element, attribute, value) that are the default ones
+const DEFAULT_ATTRS: [(&str, &str, &str); 7] = [
+    ("button", "type", "submit"),
+    ("form", "autocomplete", "on"),
+    ("form", "enctype", "application/x-www-form-urlencoded"),
+    ("form", "method", "get"),
+    ("input", "type", "text"),
+    ("script", "type", "text/javascript"),
+    ("style", "type", "text/css"),
+];
+
+/// Event handler attributes, whose value is JavaScript
+const EVENT_HANDLER_ATTRS: [&str; 116] = [
+    "onabort",
+    "onafterprint",
+    "onanimationcancel",
+    "onanimationend",
+    "onanimationiteration",
+    "onanimationstart",
+    "onauxclick",
+    "onbeforeinput",
+    "onbeforematch",
+    "onbeforeprint",
+    "onbeforetoggle",
+    "onbeforeunload",
+    "onblur",
+    "oncancel",
+    "oncanplay",
+    "oncanplaythrough",
+    "onchange",
+    "onclick",
+    "onclose",
+    "oncommand",
+    "oncontextlost",
+    "oncontextmenu",
+    "oncontextrestored",
+    "oncopy",
+    "oncuechange",
+    "oncut",
+    "ondblclick",
+    "ondrag",
+    "ondragend",
+    "ondragenter",
+    "ondragleave",
+    "ondragover",
+    "ondragstart",
+    "ondrop",
+    "ondurationchange",
+    "onemptied",
+    "onended",
+    "onerror",
+    "onfocus",
+    "onfocusin",
+    "onfocusout",
+    "onformdata",
+    "ongotpointercapture",
+    "onhashchange",
+    "oninput",
+    "oninvalid",
+    "onkeydown",
+    "onkeypress",
+    "onkeyup",
+    "onlanguagechange",
+    "onload",
+    "onloadeddata",
+    "onloadedmetadata",
+    "onloadstart",
+    "onlostpointercapture",
+    "onmessage",
+    "onmessageerror",
+    "onmousedown",
+    "onmouseenter",
+    "onmouseleave",
+    "onmousemove",
+    "onmouseout",
+    "onmouseover",
+    "onmouseup",
+    "onoffline",
+    "ononline",
+    "onpagehide",
+    "onpagereveal",
+    "onpageshow",
+    "onpageswap",
+    "onpaste",
+    "onpause",
+    "onplay",
+    "onplaying",
+    "onpointercancel",
+    "onpointerdown",
+    "onpointerenter",
+    "onpointerleave",
+    "onpointermove",
+    "onpointerout",
+    "onpointerover",
+    "onpointerup",
+    "onpopstate",
+    "onprogress",
+    "onratechange",
+    "onrejectionhandled",
+    "onreset",
+    "onresize",
+    "onscroll",
+    "onscrollend",
+    "onsecuritypolicyviolation",
+    "onseeked",
+    "onseeking",
+    "onselect",
+    "onselectionchange",
+    "onselectstart",
+    "onslotchange",
+    "onstalled",
+    "onstorage",
+    "onsubmit",
+    "onsuspend",
+    "ontimeupdate",
+    "ontoggle",
+    "ontouchcancel",
+    "ontouchend",
+    "ontouchmove",
+    "ontouchstart",
+    "ontransitioncancel",
+    "ontransitionend",
+    "ontransitionrun",
+    "ontransitionstart",
+    "onunhandledrejection",
+    "onunload",
+    "onvolumechange",
+    "onwaiting",
+    "onwheel",
+];
+
+/// Elements that are rendered as blocks, whitespace next to them can't render
+const BLOCK_TAGS: [&str; 51] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "caption",
+    "center",
+    "colgroup",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "legend",
+    "li",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "optgroup",
+    "option",
+    "p",
+    "pre",
+    "search",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
+/// Returns whether a character is HTML whitespace
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+/// Returns whether a node is text made only of whitespace
+fn is_blank(node: &Handle) -> bool {
+    match &node.data {
+        NodeData::Text { contents } => contents.borrow().chars().all(is_whitespace),
+        _ => false,
     }
-    format!("line {lines}, col {cols}")
 }
 
-fn get_attr(tag: &BytesStart, attr: &str) -> Option<Vec<u8>> {
-    tag.try_get_attribute(attr)
-        .ok()
-        .flatten()
-        .map(|a| a.value.into_owned())
+/// Elements that close an open p element
+const P_CLOSERS: [&str; 32] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hgroup",
+    "hr",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "search",
+    "section",
+    "table",
+    "ul",
+];
+
+/// Elements whose end tag closes their p children
+const P_PARENTS_NOT_CLOSING: [&str; 7] = ["a", "audio", "del", "ins", "map", "noscript", "video"];
+
+/// Script types of classic scripts (JavaScript MIME types)
+const JS_TYPES: [&str; 16] = [
+    "application/ecmascript",
+    "application/javascript",
+    "application/x-ecmascript",
+    "application/x-javascript",
+    "text/ecmascript",
+    "text/javascript",
+    "text/javascript1.0",
+    "text/javascript1.1",
+    "text/javascript1.2",
+    "text/javascript1.3",
+    "text/javascript1.4",
+    "text/javascript1.5",
+    "text/jscript",
+    "text/livescript",
+    "text/x-ecmascript",
+    "text/x-javascript",
+];
+
+/// Script types whose content is JSON
+const JSON_TYPES: [&str; 4] = [
+    "application/json",
+    "application/ld+json",
+    "importmap",
+    "speculationrules",
+];
+
+/// How the content of a script element is formatted
+enum ScriptType {
+    /// Classic script
+    Classic,
+    /// Classic script without a type or language, the content is kept as it is if it's not
+    /// JavaScript (e.g. templates)
+    Untyped,
+    /// JavaScript module
+    Module,
+    /// JSON data (e.g. JSON-LD, import maps)
+    Json,
+    /// External script or unknown type (e.g. templates), the content is kept as it is
+    Other,
+}
+
+/// Returns whether a node is an element with one of the given names
+fn is_one_of(node: Option<&Handle>, names: &[&str]) -> bool {
+    node.and_then(|n| n.name())
+        .is_some_and(|n| names.contains(&n))
 }
 
-fn move_attrs(attrs: BytesStart) -> Result<BytesStart, String> {
-    let mut new = BytesStart::new(String::from_utf8(attrs.name().as_ref().to_vec()).unwrap());
-    let attrs: Option<Vec<Attribute>> = attrs.html_attributes().map(|a| a.ok()).collect();
-    if let Some(attrs) = attrs {
-        new.extend_attributes(attrs);
-        Ok(new)
-    } else {
-        Err("Invalid attributes in this element".into())
+fn is_comment(node: Option<&Handle>) -> bool {
+    matches!(node.map(|n| &n.data), Some(NodeData::Comment { .. }))
+}
+
+/// Returns whether a node is text starting with whitespace
+fn starts_with_whitespace(node: Option<&Handle>) -> bool {
+    match node.map(|n| &n.data) {
+        Some(NodeData::Text { contents }) => contents.borrow().starts_with(is_whitespace),
+        _ => false,
     }
 }
 
+/// Returns whether an attribute's value can be written without quotes
+fn is_unquotable(value: &str) -> bool {
+    !value.is_empty()
+        && !value.chars().any(|c| {
+            matches!(
+                c,
+                '"' | '\'' | '=' | '<' | '>' | '`' | '\t' | '\n' | '\x0C' | '\r' | ' '
+            )
+        })
+}
+
 fn trim_pos<I>(src: I) -> Option<usize>
 where
     I: Iterator<Item = char>,
@@ -60,7 +394,7 @@ where
     let mut trim = None;
     for c in src {
         match c {
-            '\t' | '\n' | ' ' => {
+            '\t' | '\n' | '\r' | ' ' | '\x0C' => {
                 trim.replace(trim.unwrap_or(0) + 1);
             }
             _ => break,
@@ -69,11 +403,168 @@ where
     trim
 }
 
-#[derive(Debug)]
-enum BufType {
-    Script,
-    Style,
-    Pre,
+/// Escapes the sequences that would close a script element or start a comment in its content
+/// (`</script` and `<!--`), which the JavaScript code generator writes back unescaped
+fn escape_script(src: &str) -> String {
+    let lower = src.to_ascii_lowercase();
+    let mut out = String::with_capacity(src.len());
+    let mut i = 0;
+    while let Some(c) = src[i..].chars().next() {
+        if lower[i..].starts_with("</script") {
+            out.push_str("<\\/");
+            i += 2;
+        } else if src[i..].starts_with("<!--") {
+            out.push_str("<\\!--");
+            i += 4;
+        } else {
+            out.push(c);
+            i += c.len_utf8();
+        }
+    }
+    out
+}
+
+/// Escapes text content
+fn escape_text(src: &str) -> String {
+    let mut out = String::with_capacity(src.len());
+    for c in src.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\u{a0}' => out.push_str("&nbsp;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Escapes an attribute's value
+fn escape_attr(src: &str) -> String {
+    let mut out = String::with_capacity(src.len());
+    for c in src.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\u{a0}' => out.push_str("&nbsp;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Concatenates the text content of the children of a node
+fn text_content(node: &Handle) -> String {
+    let mut buf = String::new();
+    for child in node.children.borrow().iter() {
+        if let NodeData::Text { contents } = &child.data {
+            buf.push_str(&contents.borrow());
+        }
+    }
+    buf
+}
+
+/// Returns the indent of a nesting level
+pub fn indent(config: &Config, level: usize) -> String {
+    config
+        .html
+        .prettify_indent_kind
+        .repeat(config.html.prettify_indent_num * level)
+}
+
+/// Writes each line of the trimmed source prefixed by `indent` (except empty lines)
+pub fn write_lines(out: &mut String, src: &str, indent: &str) {
+    for l in src.trim().split('\n') {
+        if !l.is_empty() {
+            out.push_str(indent);
+        }
+        out.push_str(l);
+        out.push('\n');
+    }
+}
+
+fn is_raw_text(node: &Handle) -> bool {
+    node.name()
+        .map(|n| RAW_TEXT_TAGS.contains(&n))
+        .unwrap_or(false)
+}
+
+/// Returns whether the tag `name` starts at `at` (after `<` or `</`) in the lowercase source
+fn is_tag(lower: &str, at: usize, name: &str) -> bool {
+    lower[at..].starts_with(name)
+        && lower[at + name.len()..]
+            .chars()
+            .next()
+            .is_none_or(|c| is_whitespace(c) || c == '/' || c == '>')
+}
+
+/// Returns the offset of the `>` ending the tag starting at `start`
+fn tag_end(src: &str, start: usize) -> Option<usize> {
+    let mut quote = None;
+    let mut after_eq = false;
+    for (i, c) in src[start..].char_indices() {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '>' => return Some(start + i),
+            '"' | '\'' if after_eq => quote = Some(c),
+            _ => {}
+        }
+        if !is_whitespace(c) {
+            after_eq = c == '=';
+        }
+    }
+    None
+}
+
+/// Returns the offset of the end tag of the element `name` whose content starts at `start`, or
+/// the end of the source if it's not closed
+fn content_end(lower: &str, start: usize, name: &str) -> usize {
+    // The text content of these elements ends at the first end tag
+    if name == "plaintext" {
+        return lower.len();
+    }
+    let text = RAW_TEXT_TAGS.contains(&name) || name == "textarea" || name == "title";
+    let mut depth = 0;
+    let mut i = start;
+    while let Some(j) = lower[i..].find('<') {
+        let at = i + j;
+        if lower[at + 1..].starts_with('/') && is_tag(lower, at + 2, name) {
+            if depth == 0 {
+                return at;
+            }
+            depth -= 1;
+        } else if !text && is_tag(lower, at + 1, name) {
+            depth += 1;
+        }
+        i = at + 1;
+    }
+    lower.len()
+}
+
+/// Returns the number of nodes of a tree
+fn count_nodes(node: &Handle) -> usize {
+    1 + node
+        .children
+        .borrow()
+        .iter()
+        .map(count_nodes)
+        .sum::<usize>()
+}
+
+/// Returns the first element named `name` of a tree
+fn find_element(node: &Handle, name: &str) -> Option<Handle> {
+    node.children.borrow().iter().find_map(|child| {
+        if child.name() == Some(name) {
+            Some(child.clone())
+        } else {
+            find_element(child, name)
+        }
+    })
 }
 
 pub struct Html<'a> {
@@ -83,78 +574,59 @@ pub struct Html<'a> {
     /// Text source of the unformatted text
     src: &'a str,
 
-    /// XML reader, reads unformatted html
-    reader: Reader<&'a [u8]>,
-
     /// User configuration
     config: &'a Config,
 
-    /// XML writer
-    writer: Writer<Cursor<Vec<u8>>>,
-
-    /// What kind of buffer is being read
-    wbuf: Option<BufType>,
+    /// Formatted output
+    out: String,
 
     /// Is minifying or not
     minify: bool,
 
-    /// Whether or not sbuf should be formatted (in case the script is not javascript)
-    fmt: bool,
-
     /// Indentation level
     indent: usize,
+
+    /// Is the document in quirks mode
+    quirks: bool,
+
+    /// End of the output of a plaintext element, which can't be closed so nothing can follow it
+    plaintext_end: Option<usize>,
+
+    /// Lowercase source, to find the tags of preserved elements
+    lower: Option<String>,
+
+    /// Start of the tags of the preserved elements written from the source
+    sliced: HashSet<usize>,
 }
 
 impl<'a> Html<'a> {
     /// Initialize HTML formatter
-    /// Since the source itself is an UTF-8 string, bytes read from the reader are considered UTF-8
     pub fn new(src: &'a str, alloc: &'a Allocator, config: &'a Config) -> Self {
-        let mut reader = Reader::from_str(src);
-        reader.config_mut().check_comments = true;
-        reader.config_mut().check_end_names = false;
-        reader.config_mut().trim_markup_names_in_closing_tags = true;
-        reader.config_mut().trim_text(false);
         Html {
             alloc,
             src,
-            reader,
             config,
-            writer: Writer::new(Cursor::new(Vec::new())),
-            wbuf: None,
+            out: String::with_capacity(src.len()),
             minify: false,
-            fmt: false,
             indent: 0,
+            quirks: false,
+            plaintext_end: None,
+            lower: None,
+            sliced: HashSet::new(),
         }
     }
 
     /// Writes indented text
-    fn write_indented(&mut self, src: &str) -> Result<(), String> {
+    fn write_indented(&mut self, src: &str) {
         if src.is_empty() {
-            return Ok(());
-        }
-        let indent = self
-            .config
-            .html
-            .prettify_indent_kind
-            .repeat(self.config.html.prettify_indent_num * self.indent);
-        let writer = self.writer.get_mut();
-        for l in src.trim().split('\n') {
-            writer
-                .write_all(indent.as_bytes())
-                .map_err(|e| format!("Failed to write text indent: {e}"))?;
-            writer
-                .write_all(l.as_bytes())
-                .map_err(|e| format!("Failed to write text line: {e}"))?;
-            writer
-                .write_all(b"\n")
-                .map_err(|e| format!("Failed to write text newline: {e}"))?;
+            return;
         }
-        Ok(())
+        write_lines(&mut self.out, src, &indent(self.config, self.indent));
     }
 
     /// Writes trimmed text, while keeping spaces
-    fn write_trimmed(&mut self, src: Vec<u8>) -> Result<(), String> {
-        let mut txt = String::from_utf8(src).unwrap();
+    fn write_trimmed(&mut self, src: &str) {
+        let mut txt = escape_text(src);
         if let Some(trim_start) = trim_pos(txt.chars()) {
             txt.drain(..trim_start);
             txt.insert(0, ' ');
@@ -164,360 +636,702 @@ impl<'a> Html<'a> {
             txt.push(' ');
         }
         if txt.is_empty() {
-            return Ok(());
+            return;
         }
         if txt.trim().is_empty() {
-            self.writer
-                .get_mut()
-                .write_all(b" ")
-                .map_err(|e| format!("Failed to write text: {e}"))
+            self.out.push(' ');
         } else {
-            self.writer
-                .get_mut()
-                .write_all(txt.as_bytes())
-                .map_err(|e| format!("Failed to write text: {e}"))
+            self.out.push_str(&txt);
         }
     }
 
-    fn write_text(&mut self, src: &str) -> Result<(), String> {
-        self.writer
-            .get_mut()
-            .write_all(src.as_bytes())
-            .map_err(|e| format!("Failed to write text line: {e}"))
+    /// Writes a new line
+    fn write_newline(&mut self) {
+        self.out.push('\n');
+    }
+
+    /// Writes an indent
+    fn write_indent(&mut self) {
+        self.out.push_str(&indent(self.config, self.indent));
     }
 
-    fn write_end(&mut self, name: &str) -> Result<(), String> {
-        if !self.minify {
-            self.write_indent()?;
+    /// Formats the JavaScript of event handler attributes and the CSS of `style` attributes. A
+    /// value that can't be parsed is kept as it is, and so is a prettified value that would span
+    /// multiple lines.
+    fn attr_value<'v>(&self, name: &str, value: &'v str) -> Cow<'v, str> {
+        if !self.config.html.fmt_attrs || value.trim().is_empty() {
+            return Cow::Borrowed(value);
         }
-        self.writer
-            .write_event(Event::End(BytesEnd::new(name)))
-            .map_err(|e| format!("Failed to write end tag: {e}"))?;
-        if !self.minify {
-            self.write_newline()?;
+        let fmted = if name == "style" {
+            css::fmt_declarations(value, self.config, self.minify)
+        } else if EVENT_HANDLER_ATTRS.contains(&name) {
+            javascript::fmt_handler(value, self.alloc, self.config, self.minify)
+        } else {
+            return Cow::Borrowed(value);
+        };
+        match fmted {
+            Ok(v) if self.minify || !v.trim().contains('\n') => Cow::Owned(v.trim().to_owned()),
+            _ => Cow::Borrowed(value),
         }
-        Ok(())
     }
 
-    /// Writes and event, clones the tags if attributes are to be formatted
-    fn write_event(&mut self, event: Event) -> Result<(), String> {
-        let event = match event {
-            Event::Start(e) if self.config.html.fmt_attrs => Event::Start(move_attrs(e)?),
-            Event::Empty(e) if self.config.html.fmt_attrs => Event::Empty(move_attrs(e)?),
-            e => e,
+    /// Writes an element's start tag with its attributes
+    fn write_start(&mut self, node: &Handle) {
+        self.write_start_tag(node, false);
+    }
+
+    /// Writes an element's start tag, with its attributes as they are in the tree if `verbatim`
+    fn write_start_tag(&mut self, node: &Handle, verbatim: bool) {
+        if let NodeData::Element { name, attrs, .. } = &node.data {
+            self.out.push('<');
+            self.out.push_str(&name.local);
+            let is_html = name.ns == ns!(html);
+            for attr in attrs.borrow().iter() {
+                let value = match &attr.name.prefix {
+                    None if !verbatim => self.attr_value(&attr.name.local, &attr.value),
+                    _ => Cow::Borrowed(&*attr.value),
+                };
+                let attr_name = match &attr.name.prefix {
+                    Some(prefix) => Cow::Owned(format!("{prefix}:{}", attr.name.local)),
+                    None => Cow::Borrowed(&*attr.name.local),
+                };
+                if verbatim {
+                    self.out.push(' ');
+                    self.out.push_str(&attr_name);
+                    self.out.push_str("=\"");
+                    self.out.push_str(&escape_attr(&value));
+                    self.out.push('"');
+                } else {
+                    self.write_attr(&name.local, is_html, &attr_name, value);
+                }
+            }
+            self.out.push('>');
+        }
+    }
+
+    /// Returns whether the whitespace inside of an element is significant, so its content is
+    /// written as it is
+    fn is_preserved(&self, node: &Handle) -> bool {
+        let NodeData::Element { name, attrs, .. } = &node.data else {
+            return false;
         };
-        self.writer
-            .write_event(event)
-            .map_err(|e| format!("Failed to write event: {e}"))
+        let html = &self.config.html;
+        if html
+            .preserve_tags
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(&name.local))
+        {
+            return true;
+        }
+        attrs.borrow().iter().any(|a| {
+            a.name.prefix.is_none()
+                && (html.preserve_attrs.iter().any(|p| **p == *a.name.local)
+                    || &*a.name.local == "class"
+                        && a.value
+                            .split_ascii_whitespace()
+                            .any(|c| html.preserve_classes.iter().any(|p| p == c)))
+        })
     }
 
-    /// Writes a new line
-    fn write_newline(&mut self) -> Result<(), String> {
-        self.writer
-            .get_mut()
-            .write_all(b"\n")
-            .map_err(|e| format!("Failed to write newline: {e}"))
+    /// Writes an attribute of an element, minifying it if enabled in the configuration
+    fn write_attr(&mut self, elem: &str, is_html: bool, name: &str, mut value: Cow<str>) {
+        let html = &self.config.html;
+        if self.minify {
+            if html.uglify_rm_default_attrs
+                && is_html
+                && DEFAULT_ATTRS.iter().any(|&(e, attr, default)| {
+                    e == elem && attr == name && value.trim().eq_ignore_ascii_case(default)
+                })
+            {
+                return;
+            }
+            if html.uglify_trim_class && name == "class" {
+                value = Cow::Owned(value.split_ascii_whitespace().collect::<Vec<_>>().join(" "));
+            }
+            if html.uglify_collapse_bool_attrs
+                && (value.is_empty()
+                    || is_html
+                        && BOOLEAN_ATTRS
+                            .iter()
+                            .any(|&(e, attr)| (e == "*" || e == elem) && attr == name)
+                        && value.eq_ignore_ascii_case(name))
+            {
+                self.out.push(' ');
+                self.out.push_str(name);
+                return;
+            }
+        }
+        self.out.push(' ');
+        self.out.push_str(name);
+        self.out.push('=');
+        if self.minify && html.uglify_unquote_attrs && is_unquotable(&value) {
+            self.out.push_str(&escape_attr(&value));
+        } else {
+            self.out.push('"');
+            self.out.push_str(&escape_attr(&value));
+            self.out.push('"');
+        }
     }
 
-    /// Writes an indent
-    fn write_indent(&mut self) -> Result<(), String> {
-        let v = self
-            .config
-            .html
-            .prettify_indent_kind
-            .repeat(self.config.html.prettify_indent_num * self.indent);
-        self.writer
-            .get_mut()
-            .write_all(v.as_bytes())
-            .map_err(|e| format!("Failed to write indent: {e}"))
-    }
-
-    fn handle_buf(&mut self) -> Result<(), String> {
-        const SCRIPT: [u8; 8] = *b"/script>";
-        const STYLE: [u8; 7] = *b"/style>";
-        const PRE: [u8; 5] = *b"/pre>";
-        if let Some(buftype) = self.wbuf.take() {
-            let mut reader = self.reader.stream();
-            let mut buf: Vec<u8> = Vec::with_capacity(16384);
-            loop {
-                reader
-                    .read_until(b'<', &mut buf)
-                    .map_err(|e| format!("Failed to read {:?} buffer: {e}", buftype))?;
-                match buftype {
-                    BufType::Script => {
-                        let mut endtag = [0u8; 8];
-                        reader
-                            .read_exact(&mut endtag)
-                            .map_err(|e| format!("Failed to read </script>: {e}"))?;
-                        if endtag == SCRIPT {
-                            break;
-                        } else {
-                            buf.extend_from_slice(&endtag);
-                        }
-                    }
-                    BufType::Style => {
-                        let mut endtag = [0u8; 7];
-                        reader
-                            .read_exact(&mut endtag)
-                            .map_err(|e| format!("Failed to read </style>: {e}"))?;
-                        if endtag == STYLE {
-                            break;
-                        } else {
-                            buf.extend_from_slice(&endtag);
-                        }
-                    }
-                    BufType::Pre => {
-                        let mut endtag = [0u8; 5];
-                        reader
-                            .read_exact(&mut endtag)
-                            .map_err(|e| format!("Failed to read </pre>: {e}"))?;
-                        if endtag == PRE {
-                            break;
-                        } else {
-                            buf.extend_from_slice(&endtag);
-                        }
-                    }
+    /// Writes an element's end tag
+    fn write_end(&mut self, node: &Handle) {
+        // The content of plaintext runs to the end of the document
+        if let Some(name) = node.name()
+            && name != "plaintext"
+        {
+            self.out.push_str("</");
+            self.out.push_str(name);
+            self.out.push('>');
+        }
+    }
+
+    /// Writes a node (and its children) exactly as it appears in the tree
+    fn write_raw(&mut self, node: &Handle, raw_text: bool) {
+        match &node.data {
+            NodeData::Text { contents } if raw_text => self.out.push_str(&contents.borrow()),
+            NodeData::Text { contents } => self.out.push_str(&escape_text(&contents.borrow())),
+            NodeData::Element { .. } => {
+                self.write_start_tag(node, true);
+                if node
+                    .name()
+                    .map(|n| EMPTY_TAGS.contains(&n))
+                    .unwrap_or(false)
+                {
+                    return;
                 }
+                self.write_children_raw(node);
+                self.write_end(node);
             }
-            buf.pop();
-            let buf = String::from_utf8(buf).unwrap();
-            match buftype {
-                BufType::Script => {
-                    if self.fmt && !self.minify {
-                        let buf = javascript::fmt_str(
-                            &buf,
-                            SourceType::cjs(),
-                            self.alloc,
-                            self.config,
-                            false,
-                        )?;
-                        self.write_indented(&buf)?;
-                    } else if !self.fmt && !self.minify {
-                        self.write_indented(&buf)?;
-                    } else if self.fmt && self.minify {
-                        let buf = javascript::fmt_str(
-                            &buf,
-                            SourceType::cjs(),
-                            self.alloc,
-                            self.config,
-                            true,
-                        )?;
-                        self.write_text(&buf)?;
-                    } else if !self.fmt && self.minify {
-                        self.write_text(&buf)?;
-                    }
-                    self.fmt = false;
-                    if self.indent > 0 {
-                        self.indent -= 1;
-                    }
-                    self.write_end("script")?;
-                }
-                BufType::Style => {
-                    if self.minify {
-                        let buf = css::fmt_str(&buf, self.config, true)?;
-                        self.write_indented(&buf)?;
-                    } else {
-                        let buf = css::fmt_str(&buf, self.config, true)?;
-                        self.write_text(&buf)?;
-                    }
-                    if self.indent > 0 {
-                        self.indent -= 1;
-                    }
-                    self.write_end("style")?;
-                }
-                BufType::Pre => {
-                    self.write_text(&buf)?;
-                    if self.indent > 0 {
-                        self.indent -= 1;
-                    }
-                    self.write_end("pre")?;
+            _ => self.write_other(node),
+        }
+    }
+
+    /// Writes the children of a node exactly as they appear in the tree
+    fn write_children_raw(&mut self, node: &Handle) {
+        let children = match &node.data {
+            NodeData::Element {
+                template_contents: Some(contents),
+                ..
+            } => contents.children.borrow().clone(),
+            _ => node.children.borrow().clone(),
+        };
+        // The parser drops the first newline of these elements
+        if matches!(node.name(), Some("pre" | "textarea" | "listing"))
+            && let Some(NodeData::Text { contents }) = children.first().map(|c| &c.data)
+            && contents.borrow().starts_with('\n')
+        {
+            self.write_newline();
+        }
+        let raw_text = is_raw_text(node);
+        for child in children.iter() {
+            self.write_raw(child, raw_text);
+        }
+        if node.name() == Some("plaintext") {
+            self.plaintext_end = Some(self.out.len());
+        }
+    }
+
+    /// Writes the content of an element as it is in the source. Falls back to the content in the
+    /// tree (which gives the same tree when parsed) if it can't be found in the source, e.g. for
+    /// elements without a start tag or moved by the parser
+    fn write_preserved(&mut self, node: &Handle) {
+        match self.source_content(node) {
+            Some(content) => {
+                self.out.push_str(content);
+                if node.name() == Some("plaintext") {
+                    self.plaintext_end = Some(self.out.len());
                 }
             }
+            None => self.write_children_raw(node),
         }
-        Ok(())
     }
 
-    /// Prettifies everything
-    fn prettify_inner(&mut self) -> Result<(), String> {
-        self.minify = false;
-        let mut noindent = 0;
-        loop {
-            self.handle_buf()?;
-            match self.reader.read_event() {
-                // Checks for style or script tags
-                Ok(Event::Start(e)) if e.name().as_ref() == b"script" => {
-                    if get_attr(&e, "src").is_none() {
-                        self.wbuf.replace(BufType::Script);
-                        self.fmt = get_attr(&e, "type")
-                            .map(|a| a == b"text/javascript" || a == b"module")
-                            .unwrap_or(false);
-                    }
-                    self.write_indent()?;
-                    self.write_event(Event::Start(e))?;
-                    self.write_newline()?;
-                    self.indent += 1;
-                }
-                Ok(Event::Start(e)) if e.name().as_ref() == b"style" => {
-                    self.wbuf.replace(BufType::Style);
-                    self.write_indent()?;
-                    self.write_event(Event::Start(e))?;
-                    self.write_newline()?;
-                    self.indent += 1;
-                }
-                Ok(Event::Start(e)) if e.name().as_ref() == b"pre" => {
-                    self.wbuf.replace(BufType::Pre);
-                    self.write_indent()?;
-                    self.write_event(Event::Start(e))?;
-                    self.write_newline()?;
-                    self.indent += 1;
+    /// Returns the content of an element in the source, if its start tag is found on the line of
+    /// the element and the content gives the same element when parsed
+    fn source_content(&mut self, node: &Handle) -> Option<&'a str> {
+        let NodeData::Element { name, .. } = &node.data else {
+            return None;
+        };
+        // Foreign elements are parsed differently
+        if name.ns != ns!(html) {
+            return None;
+        }
+        let name = &*name.local;
+        let src = self.src;
+        let line_start = match node.line as usize {
+            0 | 1 => 0,
+            n => src.match_indices('\n').nth(n - 2)?.0 + 1,
+        };
+        let line_end = src[line_start..]
+            .find('\n')
+            .map_or(src.len(), |i| line_start + i);
+        let lower = self.lower.get_or_insert_with(|| src.to_ascii_lowercase());
+        // Start tags of this name on the line, the last ones may start on the previous lines
+        let mut tags = Vec::new();
+        for (start, _) in lower[..line_end].rmatch_indices('<') {
+            if !is_tag(lower, start + 1, name) || self.sliced.contains(&start) {
+                continue;
+            }
+            let Some(end) = tag_end(src, start) else {
+                continue;
+            };
+            if end < line_start {
+                break;
+            }
+            tags.push((start, end + 1, content_end(lower, end + 1, name)));
+        }
+        tags.into_iter()
+            .rev()
+            .find_map(|(start, content_start, end)| {
+                let content = &src[content_start..end];
+                let end_tag = if name == "plaintext" {
+                    String::new()
+                } else {
+                    format!("</{name}>")
+                };
+                let dom = Dom::parse_document(&format!(
+                    "{}{content}{end_tag}",
+                    &src[start..content_start]
+                ));
+                let elem = find_element(&dom.document, name)?;
+                // Nothing is left out of the element (the document, html, head and body are implied)
+                if count_nodes(&dom.document) != count_nodes(&elem) + 4 {
+                    return None;
                 }
+                let (out, plaintext_end) = (mem::take(&mut self.out), self.plaintext_end);
+                self.write_children_raw(node);
+                let tree = mem::take(&mut self.out);
+                self.write_children_raw(&elem);
+                let parsed = mem::replace(&mut self.out, out);
+                self.plaintext_end = plaintext_end;
+                (tree == parsed).then(|| {
+                    self.sliced.insert(start);
+                    content
+                })
+            })
+    }
 
-                // Exit
-                Ok(Event::Eof) => break,
-
-                // NoIndent, events inside of noindent tags are not formatted
-                Ok(Event::Start(e)) if noindent > 0 => {
-                    let name = e.name().as_ref().to_ascii_lowercase();
-                    if !EMPTY_TAGS.contains(&name.as_ref()) {
-                        self.indent += 1;
-                        noindent += 1;
-                    }
-                    self.write_event(Event::Start(e))?;
-                }
-                Ok(Event::End(e)) if noindent > 0 => {
-                    self.indent -= 1;
-                    noindent -= 1;
-                    self.write_event(Event::End(e))?;
-                    if noindent == 0 {
-                        self.write_newline()?;
-                    }
+    /// Writes doctypes, comments and processing instructions
+    fn write_other(&mut self, node: &Handle) {
+        match &node.data {
+            NodeData::Doctype {
+                name,
+                public_id,
+                system_id,
+            } => {
+                self.out.push_str("<!DOCTYPE ");
+                self.out.push_str(name);
+                if !public_id.is_empty() {
+                    self.out.push_str(" PUBLIC \"");
+                    self.out.push_str(public_id);
+                    self.out.push('"');
                 }
-                Ok(Event::Text(e)) if noindent > 0 => self.write_trimmed(e.to_vec())?,
-                Ok(e) if noindent > 0 => self.write_event(e)?,
-
-                // Handle normal tags
-                Ok(Event::Start(e)) => {
-                    self.write_indent()?;
-                    let name = e.name().as_ref().to_ascii_lowercase();
-                    if !EMPTY_TAGS.contains(&name.as_ref()) {
-                        self.indent += 1;
-                    }
-                    if self
-                        .config
-                        .html
-                        .prettify_noindent_tags
-                        .iter()
-                        .any(|t| t.as_bytes() == e.name().as_ref().to_ascii_lowercase())
-                    {
-                        noindent += 1;
-                    }
-                    self.write_event(Event::Start(e))?;
-                    if noindent == 0 {
-                        self.write_newline()?;
+                if !system_id.is_empty() {
+                    if public_id.is_empty() {
+                        self.out.push_str(" SYSTEM");
                     }
+                    self.out.push_str(" \"");
+                    self.out.push_str(system_id);
+                    self.out.push('"');
                 }
-                Ok(Event::End(e)) => {
-                    if self.indent > 0 {
-                        self.indent -= 1;
-                    }
-                    self.write_indent()?;
-                    self.write_event(Event::End(e))?;
-                    self.write_newline()?;
+                self.out.push('>');
+            }
+            NodeData::Comment { contents } => {
+                self.out.push_str("<!--");
+                self.out.push_str(contents);
+                self.out.push_str("-->");
+            }
+            NodeData::ProcessingInstruction { target, contents } => {
+                self.out.push_str("<?");
+                self.out.push_str(target);
+                self.out.push(' ');
+                self.out.push_str(contents);
+                self.out.push('>');
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the children of a node (template's children are its contents)
+    fn children(node: &Handle) -> Vec<Handle> {
+        match &node.data {
+            NodeData::Element {
+                template_contents: Some(contents),
+                ..
+            } => contents.children.borrow().clone(),
+            _ => node.children.borrow().clone(),
+        }
+    }
+
+    /// Returns how the content of a script is formatted, by its type
+    fn script_type(node: &Handle) -> ScriptType {
+        if node.attr("src").is_some() {
+            return ScriptType::Other;
+        }
+        // The legacy language attribute gives the type of scripts without one
+        let kind = match (node.attr("type"), node.attr("language")) {
+            (Some(kind), _) => kind.trim().to_ascii_lowercase(),
+            (None, Some(lang)) if !lang.is_empty() => format!("text/{}", lang.to_ascii_lowercase()),
+            (None, _) => return ScriptType::Untyped,
+        };
+        let essence = kind.split(';').next().unwrap_or_default().trim();
+        if kind.is_empty() || JS_TYPES.contains(&kind.as_str()) {
+            ScriptType::Classic
+        } else if kind == "module" {
+            ScriptType::Module
+        } else if JSON_TYPES.contains(&essence) {
+            ScriptType::Json
+        } else {
+            ScriptType::Other
+        }
+    }
+
+    /// Returns the position where the text content of an element starts in the source
+    fn content_span(&self, node: &Handle, content: &str) -> Span {
+        // The start tag ends on the element's line, the content follows it
+        let line_start = match node.line as usize {
+            0 | 1 => 0,
+            n => self
+                .src
+                .match_indices('\n')
+                .nth(n - 2)
+                .map(|(i, _)| i + 1)
+                .unwrap_or(0),
+        };
+        let start = self.src[line_start..]
+            .find(&format!(">{content}"))
+            .map(|i| line_start + i + 1)
+            .unwrap_or(line_start);
+        Span::from_offset(self.src, start)
+    }
+
+    /// Formats the content of script and style elements, returns None if it's kept as it is
+    fn fmt_content(&self, node: &Handle) -> Result<Option<String>> {
+        let buf = text_content(node);
+        let js = |source_type| {
+            javascript::fmt_str(&buf, source_type, self.alloc, self.config, self.minify)
+                .map(|js| Some(escape_script(&js)))
+        };
+        match node.name() {
+            Some("script") => match Self::script_type(node) {
+                ScriptType::Classic => js(SourceType::cjs()),
+                ScriptType::Untyped => return Ok(js(SourceType::cjs()).ok().flatten()),
+                ScriptType::Module => js(SourceType::mjs()),
+                ScriptType::Json if buf.trim().is_empty() => Ok(Some(String::new())),
+                ScriptType::Json => json::fmt_str(&buf, self.config, self.minify, false).map(Some),
+                ScriptType::Other => Ok(None),
+            },
+            Some("style") => css::fmt_str(&buf, self.config, self.minify).map(Some),
+            _ => Ok(None),
+        }
+        .map_err(|e| e.offset_by(self.content_span(node, &buf)))
+    }
+
+    /// Prettifies a node inside of a noindent tag, where nodes are not indented
+    fn prettify_inline(&mut self, node: &Handle) -> Result<()> {
+        match &node.data {
+            NodeData::Text { contents } => self.write_trimmed(&contents.borrow()),
+            NodeData::Element { .. } => {
+                if self.is_preserved(node) || is_raw_text(node) {
+                    self.write_start(node);
+                    self.write_preserved(node);
+                    self.write_end(node);
+                    return Ok(());
                 }
-                Ok(Event::Text(e)) => {
-                    self.write_indented(core::str::from_utf8(&e).unwrap().trim())?;
+                self.write_start(node);
+                if node
+                    .name()
+                    .map(|n| EMPTY_TAGS.contains(&n))
+                    .unwrap_or(false)
+                {
+                    return Ok(());
                 }
-                Ok(e) => {
-                    self.write_indent()?;
-                    self.write_event(e)?;
-                    self.write_newline()?;
+                self.indent += 1;
+                for child in Self::children(node) {
+                    self.prettify_inline(&child)?;
                 }
-                Err(e) => return Err(format!("Invalid HTML syntax: {:?}", e)),
+                self.indent -= 1;
+                self.write_end(node);
             }
+            _ => self.write_other(node),
         }
         Ok(())
     }
 
-    /// Minifies everything
-    fn minify_inner(&mut self) -> Result<(), String> {
-        self.minify = true;
-        loop {
-            self.handle_buf()?;
-            match self.reader.read_event() {
-                // Checks for style or script tags
-                Ok(Event::Start(e)) if e.name().as_ref() == b"script" => {
-                    if get_attr(&e, "src").is_none() {
-                        self.wbuf.replace(BufType::Script);
-                        self.fmt = get_attr(&e, "type")
-                            .map(|a| a == b"text/javascript" || a == b"module")
-                            .unwrap_or(false);
-                    }
-                    self.write_event(Event::Start(e))?
+    /// Prettifies a node and its children
+    fn prettify_node(&mut self, node: &Handle) -> Result<()> {
+        match &node.data {
+            NodeData::Document => {
+                for child in node.children.borrow().iter() {
+                    self.prettify_node(child)?;
                 }
-                Ok(Event::Start(e)) if e.name().as_ref() == b"style" => {
-                    self.wbuf.replace(BufType::Style);
-                    self.write_event(Event::Start(e))?
-                }
-                Ok(Event::Start(e)) if e.name().as_ref() == b"pre" => {
-                    self.wbuf.replace(BufType::Pre);
-                    self.write_event(Event::Start(e))?;
+            }
+            NodeData::Text { contents } => {
+                let txt = escape_text(&contents.borrow());
+                self.write_indented(txt.trim());
+            }
+            NodeData::Element { .. } => {
+                let name = node.name().unwrap_or_default().to_ascii_lowercase();
+                self.write_indent();
+                if EMPTY_TAGS.contains(&name.as_str()) {
+                    self.write_start(node);
+                } else if !self.is_preserved(node)
+                    && let Some(buf) = self.fmt_content(node)?
+                {
+                    self.write_start(node);
+                    self.write_newline();
+                    self.indent += 1;
+                    self.write_indented(&buf);
+                    self.indent -= 1;
+                    self.write_indent();
+                    self.write_end(node);
+                } else if self.is_preserved(node) || is_raw_text(node) {
+                    self.write_start(node);
+                    self.write_preserved(node);
+                    self.write_end(node);
+                } else if self
+                    .config
+                    .html
+                    .prettify_noindent_tags
+                    .iter()
+                    .any(|t| t.to_ascii_lowercase() == name)
+                {
+                    self.prettify_inline(node)?;
+                } else {
+                    self.write_start(node);
+                    self.write_newline();
+                    self.indent += 1;
+                    for child in Self::children(node) {
+                        self.prettify_node(&child)?;
+                    }
+                    self.indent -= 1;
+                    self.write_indent();
+                    self.write_end(node);
                 }
+                self.write_newline();
+            }
+            _ => {
+                self.write_indent();
+                self.write_other(node);
+                self.write_newline();
+            }
+        }
+        Ok(())
+    }
 
-                // Ignore comments
-                Ok(Event::Comment(_)) if self.config.html.uglify_rm_comments => continue,
+    /// Returns whether whitespace can't render next to a sibling, or at the edge of its parent if
+    /// there is no sibling
+    fn is_boundary(&self, parent: &Handle, sibling: Option<&Handle>) -> bool {
+        // Nothing inside of head renders
+        if parent.name() == Some("head") {
+            return true;
+        }
+        let node = sibling.unwrap_or(parent);
+        match &node.data {
+            NodeData::Document => true,
+            NodeData::Element { name, .. } if name.ns == ns!(html) => {
+                BLOCK_TAGS.contains(&&*name.local)
+                    || sibling.is_some()
+                        && &*name.local == "br"
+                        && self.config.html.uglify_collapse_whitespace == Whitespace::Aggressive
+            }
+            _ => false,
+        }
+    }
 
-                // Trims new lines and tabs
-                Ok(Event::Text(e)) => self.write_trimmed(e.to_vec())?,
+    /// Returns the children of a node that are written when minifying
+    fn minified_children(&self, node: &Handle) -> Vec<Handle> {
+        let mut children = Self::children(node);
+        if self.config.html.uglify_rm_comments {
+            children.retain(|c| !matches!(c.data, NodeData::Comment { .. }));
+        }
+        if self.config.html.uglify_collapse_whitespace != Whitespace::Trim {
+            let dropped: Vec<bool> = (0..children.len())
+                .map(|i| {
+                    is_blank(&children[i])
+                        && (self.is_boundary(node, i.checked_sub(1).map(|i| &children[i]))
+                            || self.is_boundary(node, children.get(i + 1)))
+                })
+                .collect();
+            let mut dropped = dropped.into_iter();
+            children.retain(|_| !dropped.next().unwrap_or(false));
+        }
+        children
+    }
 
-                // Exit
-                Ok(Event::Eof) => break,
+    /// Writes text collapsing its whitespace, which is removed next to the boundaries of blocks
+    fn write_collapsed(
+        &mut self,
+        src: &str,
+        parent: &Handle,
+        prev: Option<&Handle>,
+        next: Option<&Handle>,
+    ) {
+        let txt = escape_text(src);
+        let trim_start = self.is_boundary(parent, prev);
+        let trim_end = self.is_boundary(parent, next);
+        let content = txt.trim_matches(is_whitespace);
+        if content.is_empty() {
+            if !trim_start && !trim_end {
+                self.out.push(' ');
+            }
+            return;
+        }
+        if txt.starts_with(is_whitespace) && !trim_start {
+            self.out.push(' ');
+        }
+        if self.config.html.uglify_collapse_whitespace == Whitespace::Aggressive {
+            let words: Vec<&str> = content
+                .split(is_whitespace)
+                .filter(|w| !w.is_empty())
+                .collect();
+            self.out.push_str(&words.join(" "));
+        } else {
+            self.out.push_str(content);
+        }
+        if txt.ends_with(is_whitespace) && !trim_end {
+            self.out.push(' ');
+        }
+    }
 
-                // Handle everything else
-                Ok(e) => self.write_event(e)?,
-                Err(e) => return Err(format!("Invalid HTML syntax: {:?}", e)),
+    /// Returns whether the start tag of an element can be omitted, given its first child
+    fn can_omit_start(&self, node: &Handle, first: Option<&Handle>) -> bool {
+        let NodeData::Element { name, attrs, .. } = &node.data else {
+            return false;
+        };
+        if self.config.html.uglify_omit_tags != OmitTags::All
+            || name.ns != ns!(html)
+            || !attrs.borrow().is_empty()
+        {
+            return false;
+        }
+        match &*name.local {
+            "html" => !is_comment(first),
+            "head" => first.is_none_or(|n| matches!(n.data, NodeData::Element { .. })),
+            "body" => {
+                first.is_none()
+                    || !(starts_with_whitespace(first)
+                        || is_comment(first)
+                        || is_one_of(
+                            first,
+                            &["meta", "noscript", "link", "script", "style", "template"],
+                        ))
             }
+            _ => false,
+        }
+    }
+
+    /// Returns whether the end tag of an element can be omitted, given its parent and the node
+    /// written after it
+    fn can_omit_end(&self, node: &Handle, parent: Option<&Handle>, next: Option<&Handle>) -> bool {
+        let NodeData::Element { name, .. } = &node.data else {
+            return false;
+        };
+        if self.config.html.uglify_omit_tags == OmitTags::None || name.ns != ns!(html) {
+            return false;
+        }
+        let last = next.is_none();
+        match &*name.local {
+            "html" | "body" => !is_comment(next),
+            "head" | "caption" | "colgroup" => !is_comment(next) && !starts_with_whitespace(next),
+            "li" => last || is_one_of(next, &["li"]),
+            "dt" => is_one_of(next, &["dt", "dd"]),
+            "dd" => last || is_one_of(next, &["dt", "dd"]),
+            "p" if last => parent
+                .and_then(|p| p.name())
+                .is_none_or(|p| !P_PARENTS_NOT_CLOSING.contains(&p) && !p.contains('-')),
+            // In quirks mode tables can be inside of paragraphs
+            "p" => is_one_of(next, &P_CLOSERS) && !(self.quirks && is_one_of(next, &["table"])),
+            "rt" | "rp" => last || is_one_of(next, &["rt", "rp"]),
+            "optgroup" => last || is_one_of(next, &["optgroup", "hr"]),
+            "option" => last || is_one_of(next, &["option", "optgroup", "hr"]),
+            "thead" => is_one_of(next, &["tbody", "tfoot"]),
+            "tbody" => last || is_one_of(next, &["tbody", "tfoot"]),
+            "tfoot" => last,
+            "tr" => last || is_one_of(next, &["tr"]),
+            "td" | "th" => last || is_one_of(next, &["td", "th"]),
+            _ => false,
+        }
+    }
+
+    /// Minifies the children of a node
+    fn minify_children(&mut self, node: &Handle, children: &[Handle]) -> Result<()> {
+        for (i, child) in children.iter().enumerate() {
+            let prev = i.checked_sub(1).map(|i| &children[i]);
+            self.minify_node(child, Some(node), prev, children.get(i + 1))?;
         }
         Ok(())
     }
 
-    /// Returns the last position, depending on the last error, the last script/style tag, or the
-    /// last buffer position
-    fn position(&mut self) -> String {
-        if self.reader.error_position() != 0 {
-            position(self.src, self.reader.error_position())
-        } else {
-            position(self.src, self.reader.buffer_position())
+    /// Minifies a node and its children, `prev` and `next` are the nodes written around it
+    fn minify_node(
+        &mut self,
+        node: &Handle,
+        parent: Option<&Handle>,
+        prev: Option<&Handle>,
+        next: Option<&Handle>,
+    ) -> Result<()> {
+        match &node.data {
+            NodeData::Document => {
+                let children = self.minified_children(node);
+                self.minify_children(node, &children)?;
+            }
+            NodeData::Text { contents } => match parent {
+                Some(parent) if self.config.html.uglify_collapse_whitespace != Whitespace::Trim => {
+                    self.write_collapsed(&contents.borrow(), parent, prev, next)
+                }
+                // Trims new lines and tabs
+                _ => self.write_trimmed(&contents.borrow()),
+            },
+            NodeData::Element { .. } => {
+                let children = self.minified_children(node);
+                if !self.can_omit_start(node, children.first()) {
+                    self.write_start(node);
+                }
+                match node.name() {
+                    Some(name) if EMPTY_TAGS.contains(&name) => return Ok(()),
+                    Some("script" | "style") if !self.is_preserved(node) => {
+                        match self.fmt_content(node)? {
+                            Some(buf) => self.out.push_str(&buf),
+                            None => self.out.push_str(&text_content(node)),
+                        }
+                    }
+                    _ if self.is_preserved(node) || is_raw_text(node) => self.write_preserved(node),
+                    _ => self.minify_children(node, &children)?,
+                }
+                if !self.can_omit_end(node, parent, next) {
+                    self.write_end(node);
+                }
+            }
+            // Ignore comments
+            NodeData::Comment { .. } if self.config.html.uglify_rm_comments => {}
+            _ => self.write_other(node),
         }
+        Ok(())
     }
 
     /// Consumes itself and returns prettified text as bytes (guaranteed to be UTF-8)
-    pub fn prettify(mut self) -> Result<Vec<u8>, String> {
-        self.prettify_inner()
-            .map_err(|e| format!("At {}: {e}", self.position()))?;
-        Ok(self.writer.into_inner().into_inner())
+    pub fn prettify(mut self) -> Result<Vec<u8>> {
+        self.minify = false;
+        let dom = Dom::parse(self.src);
+        self.prettify_node(&dom.document)?;
+        Ok(self.finish())
     }
 
-    /// Consumes itself and returns minified text as bytes (guaranteed to be UTF-8)
-    pub fn minify(mut self) -> Result<Vec<u8>, String> {
-        self.minify_inner()
-            .map_err(|e| format!("At {}: {e}", self.position()))?;
-        Ok(self.writer.into_inner().into_inner())
+    /// Returns the output, without what follows a plaintext element
+    fn finish(mut self) -> Vec<u8> {
+        if let Some(end) = self.plaintext_end {
+            self.out.truncate(end);
+        }
+        self.out.into_bytes()
     }
-}
 
-pub fn fmt(
-    path: &PathBuf,
-    out_path: Option<PathBuf>,
-    config: &Config,
-    minify: bool,
-    alloc: &Allocator,
-) -> Result<(), String> {
-    let file = files::read(path)?;
-    let html = Html::new(&file, alloc, config);
-    let fmted = if minify {
-        html.minify()?
-    } else {
-        html.prettify()?
-    };
-    files::write(path, out_path, &fmted)
+    /// Consumes itself and returns minified text as bytes (guaranteed to be UTF-8)
+    pub fn minify(mut self) -> Result<Vec<u8>> {
+        self.minify = true;
+        let dom = Dom::parse(self.src);
+        self.quirks = dom.quirks_mode.get() == QuirksMode::Quirks;
+        self.minify_node(&dom.document, None, None, None)?;
+        Ok(self.finish())
+    }
 }
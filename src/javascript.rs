@@ -1,14 +1,22 @@
-use std::path::PathBuf;
+use std::path::Path;
 
 use oxc::{
     allocator::Allocator,
+    ast::ast::Program,
     codegen::{Codegen, CodegenOptions},
     minifier::{CompressOptions, MangleOptions, Minifier, MinifierOptions},
-    parser::{Parser, ParserReturn},
+    parser::{ParseOptions, Parser, ParserReturn},
+    semantic::SemanticBuilder,
     span::SourceType,
+    transformer::{
+        ESTarget, Engine, EngineTargets, EnvOptions, JsxOptions, TransformOptions, Transformer,
+    },
 };
 
-use crate::{config::Config, files};
+use crate::{
+    config::{Config, JsxRuntime},
+    error::{Error, Result, Span},
+};
 
 pub fn fmt_str(
     src: &str,
@@ -16,16 +24,197 @@ pub fn fmt_str(
     alloc: &Allocator,
     config: &Config,
     minify: bool,
-) -> Result<String, String> {
+) -> Result<String> {
+    generate(
+        src,
+        src_type,
+        alloc,
+        config,
+        minify,
+        None,
+        ParseOptions::default(),
+    )
+    .map(|(code, _)| code)
+}
+
+/// Formats the body of an inline event handler (e.g. the value of `onclick`), where `return` is
+/// allowed
+pub fn fmt_handler(src: &str, alloc: &Allocator, config: &Config, minify: bool) -> Result<String> {
+    let options = ParseOptions {
+        allow_return_outside_function: true,
+        ..ParseOptions::default()
+    };
+    let (code, _) = generate(src, SourceType::cjs(), alloc, config, minify, None, options)?;
+    Ok(code.trim_end().trim_end_matches(';').to_owned())
+}
+
+/// Minifies a source and returns the code with its source map (as JSON). `source` is the path of
+/// the source as written in the map.
+pub fn minify_with_map(
+    src: &str,
+    src_type: SourceType,
+    alloc: &Allocator,
+    config: &Config,
+    source: &Path,
+) -> Result<(String, String)> {
+    let (code, map) = generate(
+        src,
+        src_type,
+        alloc,
+        config,
+        true,
+        Some(source),
+        ParseOptions::default(),
+    )?;
+    Ok((code, map.expect("Source map not generated")))
+}
+
+/// Returns the syntax lowering needed by the browsers of a browserslist query
+fn env_options(query: &str) -> Result<EnvOptions> {
+    let mut targets = EngineTargets::try_from_query(query).map_err(|e| Error::Config {
+        path: None,
+        span: None,
+        msg: format!("Invalid targets: {e}"),
+    })?;
+    // The compatibility data has no entry for Internet Explorer (which would then need no lowering
+    // at all), so it's targeted as an ES5 engine
+    if targets.contains_key(&Engine::Ie) {
+        targets.clear();
+        targets.insert(Engine::Es, browserslist::Version(5, 0, 0));
+    }
+    Ok(EnvOptions::from(targets))
+}
+
+/// Returns the newest ECMAScript version whose syntax needs no lowering, so that the minifier
+/// doesn't bring back the lowered syntax
+fn es_target(env: &EnvOptions) -> ESTarget {
+    if env.es2015.arrow_function.is_some() {
+        ESTarget::ES5
+    } else if env.es2016.exponentiation_operator {
+        ESTarget::ES2015
+    } else if env.es2017.async_to_generator {
+        ESTarget::ES2016
+    } else if env.es2018.object_rest_spread.is_some() || env.es2018.async_generator_functions {
+        ESTarget::ES2017
+    } else if env.es2019.optional_catch_binding {
+        ESTarget::ES2018
+    } else if env.es2020.nullish_coalescing_operator || env.es2020.optional_chaining {
+        ESTarget::ES2019
+    } else if env.es2021.logical_assignment_operators {
+        ESTarget::ES2020
+    } else if env.es2022.class_static_block || env.es2022.class_properties.is_some() {
+        ESTarget::ES2021
+    } else {
+        ESTarget::ESNext
+    }
+}
+
+/// Returns how JSX is transformed when transpiling
+fn jsx_options(config: &Config) -> JsxOptions {
+    let js = &config.javascript;
+    JsxOptions {
+        runtime: match js.uglify_jsx_runtime {
+            JsxRuntime::Classic => oxc::transformer::JsxRuntime::Classic,
+            JsxRuntime::Automatic => oxc::transformer::JsxRuntime::Automatic,
+        },
+        pragma: js.uglify_jsx_pragma.clone(),
+        pragma_frag: js.uglify_jsx_pragma_frag.clone(),
+        import_source: js.uglify_jsx_import_source.clone(),
+        ..JsxOptions::enable()
+    }
+}
+
+/// Transforms a program with the oxc transformer (lowering syntax, stripping types and
+/// transforming JSX)
+fn transform<'a>(
+    program: &mut Program<'a>,
+    alloc: &'a Allocator,
+    src: &str,
+    options: &TransformOptions,
+) -> Result<()> {
+    let scoping = SemanticBuilder::new()
+        .build(program)
+        .semantic
+        .into_scoping();
+    let ret = Transformer::new(alloc, Path::new(""), options).build_with_scoping(scoping, program);
+    if let Some(e) = ret.errors.first() {
+        return Err(Error::JavaScript {
+            path: None,
+            span: e
+                .labels
+                .as_ref()
+                .and_then(|l| l.first())
+                .map(|l| Span::from_range(src, l.offset(), l.offset() + l.len())),
+            msg: format!("Failed to transform JavaScript: {e}"),
+            help: e.help.as_ref().map(|h| h.to_string()),
+        });
+    }
+    Ok(())
+}
+
+fn generate(
+    src: &str,
+    src_type: SourceType,
+    alloc: &Allocator,
+    config: &Config,
+    minify: bool,
+    source: Option<&Path>,
+    options: ParseOptions,
+) -> Result<(String, Option<String>)> {
     let ParserReturn {
         mut program,
         errors: parser_errors,
         panicked,
         ..
-    } = Parser::new(alloc, src, src_type).parse();
+    } = Parser::new(alloc, src, src_type)
+        .with_options(options)
+        .parse();
     if panicked {
+        let span = parser_errors
+            .iter()
+            .filter_map(|e| e.labels.as_ref()?.first())
+            .map(|l| Span::from_range(src, l.offset(), l.offset() + l.len()))
+            .next();
+        let help = parser_errors
+            .first()
+            .and_then(|e| e.help.as_ref())
+            .map(|h| h.to_string());
         let errors: Vec<String> = parser_errors.into_iter().map(|e| e.to_string()).collect();
-        return Err(errors.join("\t\n"));
+        return Err(Error::JavaScript {
+            path: None,
+            span,
+            msg: errors.join("\t\n"),
+            help,
+        });
+    }
+    let transpile = minify && config.javascript.uglify_transpile;
+    let (env, target) = match &config.targets {
+        Some(query) if minify => {
+            let env = env_options(query)?;
+            let target = es_target(&env);
+            (Some(env), target)
+        }
+        _ => (None, ESTarget::ESNext),
+    };
+    // The transformer strips the types of TypeScript, so it can only be lowered when transpiling
+    if env.is_some() && src_type.is_typescript() && !transpile {
+        return Err(Error::Config {
+            path: None,
+            span: None,
+            msg: "TypeScript can't be lowered to the targets without `uglify_transpile`".into(),
+        });
+    }
+    if transpile || env.is_some() {
+        let options = TransformOptions {
+            env: env.unwrap_or_default(),
+            jsx: if transpile {
+                jsx_options(config)
+            } else {
+                JsxOptions::disable()
+            },
+            ..TransformOptions::default()
+        };
+        transform(&mut program, alloc, src, &options)?;
     }
     if minify {
         let minifier = Minifier::new(MinifierOptions {
@@ -35,24 +224,26 @@ pub fn fmt_str(
                 None
             },
             compress: Some(CompressOptions {
+                target,
                 drop_debugger: config.javascript.uglify_drop_debugger,
                 drop_console: config.javascript.uglify_drop_console,
                 ..CompressOptions::default()
             }),
         })
         .build(alloc, &mut program);
-        Ok(Codegen::new()
+        let ret = Codegen::new()
             .with_options(CodegenOptions {
                 minify: true,
                 single_quote: config.javascript.use_single_quotes,
                 comments: config.javascript.uglify_remove_comments,
+                source_map_path: source.map(|p| p.to_path_buf()),
                 ..CodegenOptions::default()
             })
             .with_scoping(minifier.scoping)
-            .build(&program)
-            .code)
+            .build(&program);
+        Ok((ret.code, ret.map.map(|m| m.to_json_string())))
     } else {
-        Ok(Codegen::new()
+        let code = Codegen::new()
             .with_options(CodegenOptions {
                 minify: false,
                 single_quote: config.javascript.use_single_quotes,
@@ -60,24 +251,7 @@ pub fn fmt_str(
                 ..Default::default()
             })
             .build(&program)
-            .code)
+            .code;
+        Ok((code, None))
     }
 }
-
-pub fn fmt(
-    path: &PathBuf,
-    out_path: Option<PathBuf>,
-    config: &Config,
-    minify: bool,
-    alloc: &Allocator,
-) -> Result<(), String> {
-    let file = files::read(path)?;
-    let fmted = fmt_str(
-        &file,
-        SourceType::from_path(path).unwrap_or_else(|e| panic!("Unknown javascript extension: {e}")),
-        alloc,
-        config,
-        minify,
-    )?;
-    files::write(path, out_path, fmted.as_bytes())
-}
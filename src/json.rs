@@ -0,0 +1,395 @@
+use std::mem;
+
+use crate::{
+    config::Config,
+    error::{Error, Result, Span},
+};
+
+/// Formats JSON, or JSONC if `jsonc` (comments and trailing commas are allowed). Strings and
+/// numbers are written as they are in the source
+pub fn fmt_str(src: &str, config: &Config, minify: bool, jsonc: bool) -> Result<String> {
+    // A byte order mark is not JSON, but editors can write it
+    let src = src.strip_prefix('\u{FEFF}').unwrap_or(src);
+    let mut json = Json {
+        src,
+        pos: 0,
+        out: String::with_capacity(src.len()),
+        indent: config
+            .json
+            .prettify_indent_kind
+            .repeat(config.json.prettify_indent_num),
+        level: 0,
+        minify,
+        sort_keys: config.json.sort_keys,
+        jsonc,
+        keep_comments: !minify || !config.json.uglify_rm_comments,
+        comments: Vec::new(),
+    };
+    json.skip()?;
+    json.write_leading();
+    json.value()?;
+    json.skip()?;
+    if json.pos < src.len() {
+        return Err(json.error("Unexpected content after the JSON value"));
+    }
+    json.write_comments(true);
+    if !minify {
+        json.out.push('\n');
+    }
+    Ok(json.out)
+}
+
+/// A comment found between two tokens
+struct Comment<'a> {
+    /// Whether the comment starts on its own line, rather than after the previous token
+    own_line: bool,
+    text: &'a str,
+}
+
+/// A formatted member of an object or array
+struct Member<'a> {
+    /// Raw key of an object's member, used to sort them
+    key: &'a str,
+    /// Comments on their own line before the member, and the member
+    body: String,
+    /// Comments following the member on its line
+    trailing: String,
+}
+
+/// Deepest nesting of objects and arrays (as in serde_json), past which the source is rejected
+/// instead of overflowing the stack
+const MAX_DEPTH: usize = 128;
+
+/// Formatter writing the tokens of the source as they are parsed
+struct Json<'a> {
+    src: &'a str,
+    /// Byte offset of the next token
+    pos: usize,
+    out: String,
+    indent: String,
+    level: usize,
+    minify: bool,
+    sort_keys: bool,
+    jsonc: bool,
+    keep_comments: bool,
+    /// Comments found since the last token
+    comments: Vec<Comment<'a>>,
+}
+
+impl<'a> Json<'a> {
+    /// Creates an error at the current position
+    fn error(&self, msg: &str) -> Error {
+        let end = self.src[self.pos..]
+            .chars()
+            .next()
+            .map(|c| self.pos + c.len_utf8())
+            .unwrap_or(self.pos);
+        Error::Json {
+            path: None,
+            span: Some(Span::from_range(self.src, self.pos, end)),
+            msg: msg.to_owned(),
+            help: None,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.pos).copied()
+    }
+
+    /// Skips a byte if it's `c`
+    fn eat(&mut self, c: u8) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    /// Skips digits, returns whether there was at least one
+    fn digits(&mut self) -> bool {
+        let start = self.pos;
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+
+    /// Skips whitespace and comments, which are kept to be written later
+    fn skip(&mut self) -> Result<()> {
+        let mut own_line = false;
+        loop {
+            match self.peek() {
+                Some(b'\n') => {
+                    own_line = true;
+                    self.pos += 1;
+                }
+                Some(b' ' | b'\t' | b'\r') => self.pos += 1,
+                Some(b'/') if !self.jsonc => {
+                    return Err(self.error("Comments are only allowed in JSONC"));
+                }
+                Some(b'/') => {
+                    let start = self.pos;
+                    let rest = &self.src[start..];
+                    let len = if rest.starts_with("//") {
+                        rest.find('\n').unwrap_or(rest.len())
+                    } else if let Some(block) = rest.strip_prefix("/*") {
+                        match block.find("*/") {
+                            Some(end) => end + 4,
+                            None => return Err(self.error("Unterminated comment")),
+                        }
+                    } else {
+                        return Err(self.error("Expected a comment"));
+                    };
+                    self.pos += len;
+                    if self.keep_comments {
+                        self.comments.push(Comment {
+                            own_line,
+                            text: self.src[start..self.pos].trim_end(),
+                        });
+                    }
+                    own_line = false;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Writes a new line followed by the indent of the current level (nothing when minifying)
+    fn newline(&mut self) {
+        if !self.minify {
+            self.out.push('\n');
+            for _ in 0..self.level {
+                self.out.push_str(&self.indent);
+            }
+        }
+    }
+
+    /// Writes a comment, line comments are always followed by a new line
+    fn write_comment(&mut self, text: &str, newline_follows: bool) {
+        self.out.push_str(text);
+        if text.starts_with("//") {
+            if self.minify {
+                self.out.push('\n');
+            } else if !newline_follows {
+                self.newline();
+            }
+        } else if !self.minify && !newline_follows {
+            self.out.push(' ');
+        }
+    }
+
+    /// Writes the comments at the start of the source, each on its own line
+    fn write_leading(&mut self) {
+        for comment in mem::take(&mut self.comments) {
+            self.write_comment(comment.text, true);
+            if !self.minify {
+                self.out.push('\n');
+            }
+        }
+    }
+
+    /// Writes the comments following the last token on its line
+    fn write_trailing(&mut self) {
+        let n = self.comments.iter().take_while(|c| !c.own_line).count();
+        for comment in self.comments.drain(..n).collect::<Vec<_>>() {
+            if !self.minify {
+                self.out.push(' ');
+            }
+            self.write_comment(comment.text, true);
+        }
+    }
+
+    /// Writes the pending comments, `newline_follows` if a new line is written after them
+    fn write_comments(&mut self, newline_follows: bool) {
+        for comment in mem::take(&mut self.comments) {
+            if comment.own_line {
+                self.newline();
+            } else if !self.minify {
+                self.out.push(' ');
+            }
+            self.write_comment(comment.text, newline_follows);
+        }
+    }
+
+    /// Runs `f` writing into an empty buffer, returns what it wrote
+    fn capture(&mut self, f: impl FnOnce(&mut Self) -> Result<()>) -> Result<String> {
+        let outer = mem::take(&mut self.out);
+        let res = f(self);
+        let inner = mem::replace(&mut self.out, outer);
+        res.map(|_| inner)
+    }
+
+    fn value(&mut self) -> Result<()> {
+        match self.peek() {
+            Some(b'{') => self.container(b'}', true),
+            Some(b'[') => self.container(b']', false),
+            Some(b'"') => self.string().map(|_| ()),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => {
+                for literal in ["true", "false", "null"] {
+                    if self.src[self.pos..].starts_with(literal) {
+                        self.pos += literal.len();
+                        self.out.push_str(literal);
+                        return Ok(());
+                    }
+                }
+                Err(self.error("Expected a JSON value"))
+            }
+        }
+    }
+
+    /// Formats a member of an object or an array, with the comments before it
+    fn member(&mut self, object: bool) -> Result<Member<'a>> {
+        let mut key = "";
+        let body = self.capture(|json| {
+            json.write_comments(true);
+            json.newline();
+            if object {
+                if json.peek() != Some(b'"') {
+                    return Err(json.error("Expected a string key"));
+                }
+                key = json.string()?;
+                json.skip()?;
+                json.write_comments(false);
+                if !json.eat(b':') {
+                    return Err(json.error("Expected ':'"));
+                }
+                json.out.push(':');
+                if !json.minify {
+                    json.out.push(' ');
+                }
+                json.skip()?;
+                json.write_comments(false);
+            }
+            json.value()?;
+            json.skip()?;
+            // Keeps comments between the member and the comma before the comma
+            if json.peek() == Some(b',') {
+                json.write_comments(false);
+            }
+            Ok(())
+        })?;
+        Ok(Member {
+            key,
+            body,
+            trailing: String::new(),
+        })
+    }
+
+    /// Formats an object or an array, writing each member on its own line
+    fn container(&mut self, close: u8, object: bool) -> Result<()> {
+        if self.level == MAX_DEPTH {
+            return Err(self.error("Too deeply nested"));
+        }
+        self.out.push(self.src.as_bytes()[self.pos] as char);
+        self.pos += 1;
+        self.level += 1;
+        self.skip()?;
+        let mut head = String::new();
+        let mut members: Vec<Member> = Vec::new();
+        let mut comma = false;
+        loop {
+            let trailing = self.capture(|json| {
+                json.write_trailing();
+                Ok(())
+            })?;
+            match members.last_mut() {
+                Some(member) => member.trailing = trailing,
+                None => head = trailing,
+            }
+            if self.peek() == Some(close) {
+                if comma && !self.jsonc {
+                    return Err(self.error("Trailing commas are only allowed in JSONC"));
+                }
+                break;
+            } else if !members.is_empty() && !comma {
+                return Err(self.error(if object {
+                    "Expected ',' or '}'"
+                } else {
+                    "Expected ',' or ']'"
+                }));
+            }
+            members.push(self.member(object)?);
+            comma = self.eat(b',');
+            if comma {
+                self.skip()?;
+            }
+        }
+        self.pos += 1;
+        let empty = head.is_empty() && members.is_empty() && self.comments.is_empty();
+        if object && self.sort_keys {
+            members.sort_by_key(|m| m.key);
+        }
+        self.out.push_str(&head);
+        let last = members.len().saturating_sub(1);
+        for (i, member) in members.into_iter().enumerate() {
+            self.out.push_str(&member.body);
+            if i < last {
+                self.out.push(',');
+            }
+            self.out.push_str(&member.trailing);
+        }
+        self.write_comments(true);
+        self.level -= 1;
+        if !empty {
+            self.newline();
+        }
+        self.out.push(close as char);
+        Ok(())
+    }
+
+    /// Writes a string and returns it, with its quotes
+    fn string(&mut self) -> Result<&'a str> {
+        let start = self.pos;
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => {
+                            self.pos += 1
+                        }
+                        Some(b'u') => {
+                            self.pos += 1;
+                            for _ in 0..4 {
+                                if !self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                                    return Err(self.error("Invalid unicode escape"));
+                                }
+                                self.pos += 1;
+                            }
+                        }
+                        _ => return Err(self.error("Invalid escape")),
+                    }
+                }
+                Some(0..=0x1f) | None => return Err(self.error("Unterminated string")),
+                Some(_) => self.pos += 1,
+            }
+        }
+        self.pos += 1;
+        let src = self.src;
+        let string = &src[start..self.pos];
+        self.out.push_str(string);
+        Ok(string)
+    }
+
+    fn number(&mut self) -> Result<()> {
+        let start = self.pos;
+        self.eat(b'-');
+        if !self.eat(b'0') && !self.digits() {
+            return Err(self.error("Invalid number"));
+        }
+        if self.eat(b'.') && !self.digits() {
+            return Err(self.error("Expected digits after '.'"));
+        }
+        if self.eat(b'e') || self.eat(b'E') {
+            let _ = self.eat(b'+') || self.eat(b'-');
+            if !self.digits() {
+                return Err(self.error("Expected digits in the exponent"));
+            }
+        }
+        self.out.push_str(&self.src[start..self.pos]);
+        Ok(())
+    }
+}
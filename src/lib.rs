@@ -1,18 +1,36 @@
-#[doc = include_str!("../README.md")]
+#![doc = include_str!("../README.md")]
 
+mod cache;
 mod config;
 mod css;
+mod dom;
+mod error;
 mod files;
 mod fmt;
 mod html;
 mod javascript;
+mod json;
+#[cfg(test)]
+mod tests;
+mod watch;
+mod xml;
 
 // Re-export Oxc for the allocator
 pub use oxc;
 
-pub use fmt::{all, file};
+pub use error::{Error, Span};
+pub use fmt::{Language, Mode, Report, all, check, check_file, diff, diff_file, file, format_str};
+pub use watch::watch;
 
 /// Configuration options
 pub mod conf {
-    pub use super::config::{Config, Css, Html, IndentKind, JavaScript};
+    pub use super::config::{
+        Config, Css, Html, IndentKind, JavaScript, Json, JsxRuntime, OmitTags, SourceMap,
+        Whitespace, Xml,
+    };
+}
+
+/// Files of a project
+pub mod project {
+    pub use super::files::{Filter, get_extension, revtraverse};
 }
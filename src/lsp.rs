@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
+use lsp_types::{
+    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentFormattingParams, DocumentRangeFormattingParams, OneOf,
+    Position, PublishDiagnosticsParams, Range, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Url,
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+        PublishDiagnostics,
+    },
+    request::{Formatting, RangeFormatting, Request as _},
+};
+use serde_json::Value;
+use similar::{DiffTag, TextDiff};
+
+use wwwfmt::{Error, Language, Mode, conf::Config, format_str, project};
+
+/// Language server formatting the documents opened by the editor
+struct Server {
+    conn: Connection,
+    /// Content of the open documents
+    docs: HashMap<Url, String>,
+}
+
+/// Runs the language server over stdio, until the editor asks to exit
+pub fn run() -> Result<(), String> {
+    let (conn, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    };
+    conn.initialize(serde_json::to_value(capabilities).expect("Capabilities serialization failed"))
+        .map_err(|e| format!("LSP initialization failed: {e}"))?;
+    let mut server = Server {
+        conn,
+        docs: HashMap::new(),
+    };
+    server.main_loop()?;
+    // Closes stdout so that the writer thread can end
+    drop(server);
+    io_threads
+        .join()
+        .map_err(|e| format!("LSP connection failed: {e}"))
+}
+
+/// Returns the language and configuration of a document, or None if it's not supported. The
+/// configuration is found starting from the document's directory, the default one is used if
+/// there is none.
+pub fn document_info(uri: &Url) -> Result<Option<(Language, Config)>, Error> {
+    let Some((path, lang)) = uri.to_file_path().ok().and_then(|path| {
+        let lang = Language::from_extension(&project::get_extension(&path)?)?;
+        Some((path, lang))
+    }) else {
+        return Ok(None);
+    };
+    let config = match path
+        .ancestors()
+        .skip(1)
+        .find(|d| d.is_dir())
+        .and_then(|dir| project::revtraverse(dir.to_path_buf(), ".wwwfmt.toml").ok())
+    {
+        Some(config) => Config::open(config)?,
+        None => Config::default(),
+    };
+    Ok(Some((lang, config)))
+}
+
+/// Converts the position of an error in `src` into an LSP range, whose columns are in UTF-16
+/// code units
+fn error_range(e: &Error, src: &str) -> Range {
+    let Some(span) = e.span() else {
+        return Range::default();
+    };
+    let line = span.line - 1;
+    let text = src.split('\n').nth(line).unwrap_or_default();
+    let col = |chars: usize| text.chars().take(chars).map(char::len_utf16).sum::<usize>() as u32;
+    Range::new(
+        Position::new(line as u32, col(span.col - 1)),
+        Position::new(line as u32, col(span.col - 1 + span.len)),
+    )
+}
+
+/// Returns the edits that turn `old` into `new`, keeping only the changed lines that touch the
+/// lines from `start` to `end` (included) if specified
+pub fn edits(old: &str, new: &str, lines: Option<(u32, u32)>) -> Vec<TextEdit> {
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    TextDiff::from_lines(old, new)
+        .ops()
+        .iter()
+        .map(|op| op.as_tag_tuple())
+        .filter(|(tag, _, _)| *tag != DiffTag::Equal)
+        .filter(|(_, old, _)| {
+            lines.is_none_or(|(start, end)| {
+                old.start as u32 <= end && (old.end as u32 > start || old.is_empty())
+            })
+        })
+        .map(|(_, old, new)| TextEdit {
+            range: Range::new(
+                Position::new(old.start as u32, 0),
+                Position::new(old.end as u32, 0),
+            ),
+            new_text: new_lines[new].concat(),
+        })
+        .collect()
+}
+
+impl Server {
+    fn main_loop(&mut self) -> Result<(), String> {
+        while let Ok(msg) = self.conn.receiver.recv() {
+            match msg {
+                Message::Request(req) => {
+                    let shutdown = self
+                        .conn
+                        .handle_shutdown(&req)
+                        .map_err(|e| format!("LSP connection failed: {e}"))?;
+                    if shutdown {
+                        return Ok(());
+                    }
+                    let resp = self.request(req);
+                    self.send(Message::Response(resp));
+                }
+                Message::Notification(not) => self.notification(not),
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&self, msg: Message) {
+        // The editor went away, the main loop will end
+        let _ = self.conn.sender.send(msg);
+    }
+
+    fn request(&mut self, req: Request) -> Response {
+        let (id, lines, uri) = match req.method.as_str() {
+            Formatting::METHOD => {
+                match serde_json::from_value::<DocumentFormattingParams>(req.params) {
+                    Ok(params) => (req.id, None, params.text_document.uri),
+                    Err(e) => return invalid_params(req.id, e),
+                }
+            }
+            RangeFormatting::METHOD => {
+                match serde_json::from_value::<DocumentRangeFormattingParams>(req.params) {
+                    Ok(params) => {
+                        let Range { start, end } = params.range;
+                        // A selection ending at the start of a line doesn't include that line
+                        let end = if end.character == 0 && end.line > start.line {
+                            end.line - 1
+                        } else {
+                            end.line
+                        };
+                        (req.id, Some((start.line, end)), params.text_document.uri)
+                    }
+                    Err(e) => return invalid_params(req.id, e),
+                }
+            }
+            _ => {
+                return Response::new_err(
+                    req.id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("Unsupported method: {}", req.method),
+                );
+            }
+        };
+        self.format(id, &uri, lines)
+    }
+
+    /// Formats a document, publishing its errors as diagnostics
+    fn format(&self, id: RequestId, uri: &Url, lines: Option<(u32, u32)>) -> Response {
+        match self.format_doc(uri) {
+            Some((src, Ok(fmted))) => Response::new_ok(id, edits(src, &fmted, lines)),
+            Some((_, Err(e))) => {
+                Response::new_err(id, ErrorCode::RequestFailed as i32, e.to_string())
+            }
+            None => Response::new_ok(id, Value::Null),
+        }
+    }
+
+    /// Formats an open document and publishes its errors, or the ones of its configuration.
+    /// Returns the document and the result, or None if it's not open or not supported.
+    fn format_doc(&self, uri: &Url) -> Option<(&str, Result<String, Error>)> {
+        let src = self.docs.get(uri)?;
+        let res = document_info(uri)
+            .transpose()?
+            .and_then(|(lang, config)| format_str(src, lang, &config, Mode::Prettify));
+        let diagnostics = match &res {
+            Ok(_) => vec![],
+            Err(e) => vec![diagnostic(e, src)],
+        };
+        self.publish(uri, diagnostics);
+        Some((src, res))
+    }
+
+    fn notification(&mut self, not: Notification) {
+        let uri = match not.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let Ok(params) = serde_json::from_value::<DidOpenTextDocumentParams>(not.params)
+                else {
+                    return;
+                };
+                let doc = params.text_document;
+                self.docs.insert(doc.uri.clone(), doc.text);
+                doc.uri
+            }
+            DidChangeTextDocument::METHOD => {
+                let Ok(mut params) =
+                    serde_json::from_value::<DidChangeTextDocumentParams>(not.params)
+                else {
+                    return;
+                };
+                // Only full syncs are requested, so the last change is the whole document
+                let Some(change) = params.content_changes.pop() else {
+                    return;
+                };
+                let uri = params.text_document.uri;
+                self.docs.insert(uri.clone(), change.text);
+                uri
+            }
+            DidCloseTextDocument::METHOD => {
+                if let Ok(params) = serde_json::from_value::<DidCloseTextDocumentParams>(not.params)
+                {
+                    self.docs.remove(&params.text_document.uri);
+                    self.publish(&params.text_document.uri, vec![]);
+                }
+                return;
+            }
+            _ => return,
+        };
+        self.format_doc(&uri);
+    }
+
+    fn publish(&self, uri: &Url, diagnostics: Vec<Diagnostic>) {
+        let params = PublishDiagnosticsParams::new(uri.clone(), diagnostics, None);
+        self.send(Message::Notification(Notification::new(
+            PublishDiagnostics::METHOD.into(),
+            params,
+        )));
+    }
+}
+
+/// Converts an error of the document `src` into a diagnostic. Errors of other files (i.e. the
+/// configuration) are shown at the start of the document, with their path and position.
+pub fn diagnostic(e: &Error, src: &str) -> Diagnostic {
+    let (mut msg, range) = match e.path() {
+        Some(_) => (e.to_string(), Range::default()),
+        None => (e.msg().to_owned(), error_range(e, src)),
+    };
+    if let Some(help) = e.help() {
+        msg.push_str(&format!("\nhelp: {help}"));
+    }
+    Diagnostic {
+        range,
+        severity: Some(DiagnosticSeverity::ERROR),
+        source: Some("wwwfmt".into()),
+        message: msg,
+        ..Default::default()
+    }
+}
+
+fn invalid_params(id: RequestId, e: serde_json::Error) -> Response {
+    Response::new_err(id, ErrorCode::InvalidParams as i32, e.to_string())
+}
@@ -1,17 +1,15 @@
-mod config;
-mod css;
-mod files;
-mod fmt;
-mod html;
-mod javascript;
 #[cfg(test)]
-mod tests;
+mod cli_tests;
+mod lsp;
 
-use std::env;
+use std::borrow::Borrow;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::{env, fs};
 
 use argh::FromArgs;
-use config::Config;
+use wwwfmt::{Error, Language, Mode, Report, conf::Config, project};
 
 #[derive(FromArgs, Clone)]
 #[argh(help_triggers("-h", "--help"))]
@@ -44,22 +42,63 @@ struct Cli {
     /// prettifies file(s) in new file(s)
     #[argh(switch)]
     no_inplace: bool,
+    /// lists the files that would change without writing them
+    #[argh(switch)]
+    check: bool,
+    /// prints the diff of the changes without writing them
+    #[argh(switch)]
+    diff: bool,
+    /// number of threads formatting files with --all (by default all cores)
+    #[argh(option, short = 'j', default = "0")]
+    jobs: usize,
+    /// formats all files with --all, even if they didn't change since the last run
+    #[argh(switch)]
+    no_cache: bool,
+    /// formats all files, then formats again the files that change until interrupted
+    #[argh(switch)]
+    watch: bool,
+    /// reads the source from stdin and writes the result to stdout
+    #[argh(switch)]
+    stdin: bool,
+    /// language of the source read from stdin, as a file extension (e.g. tsx)
+    #[argh(option)]
+    lang: Option<String>,
+    /// path of the source read from stdin, used to get its language and configuration
+    #[argh(option)]
+    stdin_filepath: Option<String>,
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(FromArgs, Clone)]
+#[argh(subcommand)]
+enum Command {
+    Lsp(Lsp),
 }
 
+#[derive(FromArgs, Clone)]
+#[argh(subcommand, name = "lsp")]
+/// Run a language server over stdio, formatting the documents opened in the editor
+struct Lsp {}
+
 fn main() -> ExitCode {
     let cmd: Cli = argh::from_env();
     match handle(cmd) {
         Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
-            println!("{e}");
+            eprintln!("{e}");
             ExitCode::FAILURE
         }
     }
 }
 
 fn handle(cmd: Cli) -> Result<(), String> {
+    if let Some(Command::Lsp(_)) = cmd.command {
+        return lsp::run();
+    }
+
     if cmd.write_default {
-        Config::write_default()?;
+        Config::write_default().map_err(report)?;
         return Ok(());
     }
 
@@ -70,30 +109,230 @@ fn handle(cmd: Cli) -> Result<(), String> {
         _ => return Err("You must choose either --uglify or --minify".into()),
     };
 
+    // The configuration of stdin is looked up from its file path, if any
+    let stdin_path = match &cmd.stdin_filepath {
+        Some(path) => Some(
+            env::current_dir()
+                .map_err(|e| format!("Could not get current working directory: {e}"))?
+                .join(path),
+        ),
+        None => None,
+    };
+
     // Get config
     let cfg = if let Some(path) = &cmd.cfg {
-        Config::open(path).map_err(|e| format!("Failed to open config: {e}"))?
+        Config::open(path).map_err(report)?
     } else if cmd.default_cfg {
         Config::default()
+    } else if let Some(dir) = stdin_path
+        .as_ref()
+        .and_then(|p| p.ancestors().skip(1).find(|d| d.is_dir()))
+    {
+        let (conf, _) = Config::find_from(dir.to_path_buf()).map_err(report)?;
+        conf
     } else {
-        let (conf, _) =
-            Config::find().map_err(|e| format!("Failed to find and open config: {e}"))?;
+        let (conf, _) = Config::find().map_err(report)?;
         conf
     };
 
+    // Format stdin to stdout
+    if cmd.stdin || cmd.stdin_filepath.is_some() {
+        let fmted = fmt_stdin(io::stdin(), &cmd, &cfg, minify)?;
+        print!("{fmted}");
+        return Ok(());
+    }
+
+    // Skip a single file excluded by the configuration
+    if !cmd.all
+        && let Some(path) = &cmd.file
+        && is_filtered(path, &cfg)?
+    {
+        println!("{path} is ignored by the configuration");
+        return Ok(());
+    }
+
+    // Show the changes
+    if cmd.diff {
+        let diffs = if cmd.all {
+            wwwfmt::diff(project_root()?, &cfg, minify).map_err(report)?
+        } else if let Some(path) = cmd.file {
+            wwwfmt::diff_file(&path, &cfg, minify, None)
+                .map_err(report)?
+                .map(|diff| vec![(PathBuf::from(path), Ok(diff))])
+                .unwrap_or_default()
+        } else {
+            return Err(
+                "You must specify what you want to diff (either --all or a --file).".into(),
+            );
+        };
+        let mut failed = 0;
+        for (_, diff) in diffs {
+            match diff {
+                Ok(diff) => print!("{diff}"),
+                Err(e) => {
+                    failed += 1;
+                    eprintln!("{}\n", report(e));
+                }
+            }
+        }
+        return if failed == 0 {
+            Ok(())
+        } else {
+            Err(format!("{failed} file(s) failed to diff"))
+        };
+    }
+
+    // Check the formatting
+    if cmd.check {
+        let (differ, failed) = if cmd.all {
+            let report = wwwfmt::check(project_root()?, &cfg, minify, inplace).map_err(report)?;
+            (report.formatted, report.failed)
+        } else if let Some(path) = cmd.file {
+            let path = PathBuf::from(path);
+            if wwwfmt::check_file(&path, None, &cfg, minify, inplace, None).map_err(report)? {
+                (vec![path], vec![])
+            } else {
+                (vec![], vec![])
+            }
+        } else {
+            return Err(
+                "You must specify what you want to check (either --all or a --file).".into(),
+            );
+        };
+        for path in &differ {
+            println!("{}", path.display());
+        }
+        for (_, e) in &failed {
+            eprintln!("{}\n", report(e));
+        }
+        return match (differ.len(), failed.len()) {
+            (0, 0) => Ok(()),
+            (n, 0) => Err(format!("{n} file(s) would be formatted")),
+            (n, f) => Err(format!(
+                "{n} file(s) would be formatted, {f} failed to check"
+            )),
+        };
+    }
+
+    // Keep formatting
+    if cmd.watch {
+        let root = project_root()?;
+        println!("Watching {}", root.display());
+        return wwwfmt::watch(
+            root,
+            &cfg,
+            minify,
+            inplace,
+            cmd.jobs,
+            !cmd.no_cache,
+            |summary| print_summary(&summary),
+        )
+        .map_err(report);
+    }
+
     // Do the formatting
     if cmd.all {
-        let cur_dir = env::current_dir()
-            .map_err(|e| format!("Could not get current working directory: {e}"))?;
-        let mut root = files::revtraverse(cur_dir, ".wwwfmt.toml")
-            .map_err(|e| format!("Could not get project's root directory: {e}"))?;
-        root.pop();
-        fmt::all(root, &cfg, minify, inplace)
+        let summary = wwwfmt::all(
+            project_root()?,
+            &cfg,
+            minify,
+            inplace,
+            cmd.jobs,
+            !cmd.no_cache,
+        )
+        .map_err(report)?;
+        print_summary(&summary);
+        if summary.is_ok() {
+            Ok(())
+        } else {
+            Err(format!("{} file(s) failed to format", summary.failed.len()))
+        }
     } else if let Some(path) = cmd.file {
-        fmt::file(path, None, &cfg, minify, inplace, None)
-            .map_err(|e| format!("Failed to format file: {e}"))
+        wwwfmt::file(path, None, &cfg, minify, inplace, None)
+            .map_err(report)
             .map(|_| ())
     } else {
         Err("You must specify what you want to format (either --all or a --file).".into())
     }
 }
+
+/// Formats the source read from `input` in the language given by --lang or --stdin-filepath
+fn fmt_stdin(
+    mut input: impl Read,
+    cmd: &Cli,
+    cfg: &Config,
+    minify: bool,
+) -> Result<String, String> {
+    let ext = match (&cmd.lang, &cmd.stdin_filepath) {
+        (Some(lang), _) => lang.to_lowercase(),
+        (None, Some(path)) => project::get_extension(Path::new(path))
+            .ok_or("Could not get the language from the extension of --stdin-filepath")?,
+        (None, None) => {
+            return Err(
+                "You must specify the language of stdin (either --lang or --stdin-filepath)."
+                    .into(),
+            );
+        }
+    };
+    let lang = Language::from_extension(&ext).ok_or(format!("Unknown language: {ext}"))?;
+    let mut src = String::new();
+    input
+        .read_to_string(&mut src)
+        .map_err(|e| format!("Could not read stdin: {e}"))?;
+    let mode = if minify { Mode::Minify } else { Mode::Prettify };
+    wwwfmt::format_str(&src, lang, cfg, mode).map_err(|e| {
+        let e = match &cmd.stdin_filepath {
+            Some(path) => e.with_path(path),
+            None => e,
+        };
+        e.report(&src).trim_end().to_owned()
+    })
+}
+
+/// Prints the failures of a run and how many files were formatted
+fn print_summary(summary: &Report) {
+    for (_, e) in &summary.failed {
+        eprintln!("{}\n", report(e));
+    }
+    println!(
+        "{} file(s) formatted, {} unchanged, {} skipped, {} failed",
+        summary.formatted.len(),
+        summary.unchanged.len(),
+        summary.skipped.len(),
+        summary.failed.len()
+    );
+}
+
+/// Renders an error as a diagnostic, showing the source that caused it if possible
+fn report<E: Borrow<Error>>(e: E) -> String {
+    let e = e.borrow();
+    let src = e.path().and_then(|p| fs::read_to_string(p).ok());
+    match (src, e.span()) {
+        (Some(src), Some(_)) => e.report(&src).trim_end().to_owned(),
+        _ => e.to_string(),
+    }
+}
+
+/// Returns whether a file is excluded by the ignore/include patterns of the configuration. Files
+/// outside of a project are never excluded.
+fn is_filtered(path: &str, cfg: &Config) -> Result<bool, String> {
+    let Ok(root) = project_root() else {
+        return Ok(false);
+    };
+    let path = env::current_dir()
+        .map_err(|e| format!("Could not get current working directory: {e}"))?
+        .join(path);
+    Ok(!project::Filter::new(&root, cfg)
+        .map_err(report)?
+        .allows(&path))
+}
+
+/// Returns the project's root directory (where .wwwfmt.toml is)
+fn project_root() -> Result<PathBuf, String> {
+    let cur_dir =
+        env::current_dir().map_err(|e| format!("Could not get current working directory: {e}"))?;
+    let mut root = project::revtraverse(cur_dir, ".wwwfmt.toml")
+        .map_err(|e| format!("Could not get project's root directory: {e}"))?;
+    root.pop();
+    Ok(root)
+}
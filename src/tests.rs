@@ -1,14 +1,19 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
 use std::path::PathBuf;
 
-use include_dir::{include_dir, Dir};
+use include_dir::{Dir, include_dir};
 use oxc::allocator::Allocator;
 use pretty_assertions::assert_eq;
 use testdir::testdir;
 
-use crate::fmt;
-use crate::{config::Config, html::Html};
+use crate::error::{Error, Span};
+use crate::fmt::{self, Language, Mode};
+use crate::{
+    config::{Config, JsxRuntime, OmitTags, SourceMap, Whitespace},
+    files,
+    html::Html,
+};
 
 static ASSETS: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/assets");
 
@@ -60,6 +65,779 @@ fn test_tree() {
     }
     copy_files_to(path.clone());
     let config = Config::default();
-    fmt::all(path.clone(), &config, true, false).expect("Minify failed");
-    fmt::all(path, &config, false, true).expect("Prettify failed");
+    let report = fmt::all(path.clone(), &config, true, false, 0, false).expect("Minify failed");
+    assert!(report.is_ok(), "Minify failed: {:?}", report.failed);
+    let report = fmt::all(path, &config, false, true, 2, false).expect("Prettify failed");
+    assert!(report.is_ok(), "Prettify failed: {:?}", report.failed);
+}
+
+#[test]
+fn test_tree_errors() {
+    let path = testdir!();
+    copy_files_to(path.clone());
+    for name in ["broken1.js", "broken2.css"] {
+        File::create_new(path.join(name))
+            .unwrap()
+            .write_all(b"a {{{ (")
+            .unwrap();
+    }
+    File::create_new(path.join("notes.txt")).unwrap();
+    let config = Config::default();
+    let report =
+        fmt::all(path.clone(), &config, true, true, 4, false).expect("Listing files failed");
+    let failed: Vec<_> = report.failed.iter().map(|(p, _)| p.clone()).collect();
+    assert_eq!(failed, [path.join("broken1.js"), path.join("broken2.css")]);
+    assert_eq!(report.skipped, [path.join("notes.txt")]);
+    assert!(report.formatted.contains(&path.join("example.js")));
+    // The other files were formatted anyway
+    assert!(!fmt::check_file(path.join("example.js"), None, &config, true, true, None).unwrap());
+}
+
+#[test]
+fn test_cache() {
+    let path = testdir!();
+    copy_files_to(path.clone());
+    let config = Config::default();
+    let report = fmt::all(path.clone(), &config, true, true, 0, true).expect("Minify failed");
+    assert!(report.is_ok(), "Minify failed: {:?}", report.failed);
+    assert!(report.unchanged.is_empty());
+    let (formatted, skipped) = (report.formatted, report.skipped);
+
+    // The cache file written by the first run is not part of the project
+    let report = fmt::all(path.clone(), &config, true, true, 0, true).expect("Minify failed");
+    assert!(report.formatted.is_empty());
+    assert_eq!(report.unchanged, formatted);
+    assert_eq!(report.skipped, skipped);
+
+    fs::write(path.join("example.js"), "let a = 1;").unwrap();
+    let report = fmt::all(path.clone(), &config, true, true, 0, true).expect("Minify failed");
+    assert_eq!(report.formatted, [path.join("example.js")]);
+
+    // Changing the mode invalidates the cache, and so does bypassing it
+    let report = fmt::all(path.clone(), &config, false, true, 0, true).expect("Prettify failed");
+    assert_eq!(report.formatted, formatted);
+    let report = fmt::all(path, &config, false, true, 0, false).expect("Prettify failed");
+    assert_eq!(report.formatted, formatted);
+}
+
+#[test]
+fn test_source_map() {
+    let path = testdir!();
+    copy_files_to(path.clone());
+    fs::write(path.join("style.css"), "a {\n  color: red;\n}\n").unwrap();
+    let mut config = Config::default();
+    config.javascript.uglify_source_map = SourceMap::Linked;
+    config.css.uglify_source_map = SourceMap::Inline;
+
+    let js = path.join("example.js");
+    fmt::file(&js, None, &config, true, false, None).expect("Minify failed");
+    let min = fs::read_to_string(path.join("example.min.js")).unwrap();
+    assert!(min.ends_with("\n//# sourceMappingURL=example.min.js.map"));
+    let map = fs::read_to_string(path.join("example.min.js.map")).unwrap();
+    assert!(map.contains(r#""sources":["example.js"]"#), "{map}");
+    assert!(!fmt::check_file(&js, None, &config, true, false, None).expect("Check failed"));
+
+    let css = path.join("style.css");
+    fmt::file(&css, None, &config, true, false, None).expect("Minify failed");
+    let min = fs::read_to_string(path.join("style.min.css")).unwrap();
+    assert!(min.starts_with("a{color:red}\n/*# sourceMappingURL=data:application/json;base64,"));
+    assert!(!path.join("style.min.css.map").exists());
+}
+
+#[test]
+fn test_watch() {
+    let path = testdir!();
+    let js = path.join("a.js");
+    fs::write(&js, "let a = 1;\nf(a);\n").unwrap();
+    let mut config = Config::default();
+    config.javascript.uglify_source_map = SourceMap::Linked;
+    let (tx, rx) = std::sync::mpsc::channel();
+    let root = path.clone();
+    std::thread::spawn(move || {
+        crate::watch::watch(root, &config, true, true, 1, false, |report| {
+            let _ = tx.send(report);
+        })
+    });
+    let timeout = std::time::Duration::from_secs(5);
+    let report = rx
+        .recv_timeout(timeout)
+        .expect("No report of the first run");
+    assert_eq!(report.formatted, std::slice::from_ref(&js));
+    assert!(path.join("a.js.map").exists());
+
+    fs::write(&js, "let b = 2;\nf(b);\n").unwrap();
+    let report = rx.recv_timeout(timeout).expect("No report of the change");
+    assert_eq!(report.formatted, std::slice::from_ref(&js));
+    assert!(report.failed.is_empty());
+    // Neither the file formatted in place nor its source map are formatted again
+    assert!(rx.recv_timeout(std::time::Duration::from_secs(1)).is_err());
+}
+
+#[test]
+fn test_watch_events() {
+    use notify::{
+        Event, EventKind,
+        event::{CreateKind, DataChange, MetadataKind, ModifyKind, RemoveKind},
+    };
+    use std::collections::HashMap;
+
+    let path = testdir!();
+    let (a, b, c) = (path.join("a.js"), path.join("b.js"), path.join("c.js"));
+    let (tx, rx) = std::sync::mpsc::channel();
+    for event in [
+        Ok(Event::new(EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any))).add_path(b)),
+        Err(notify::Error::generic("lost")),
+        Ok(Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Any))).add_path(c.clone())),
+        Ok(Event::new(EventKind::Remove(RemoveKind::File)).add_path(a.clone())),
+    ] {
+        tx.send(event).unwrap();
+    }
+    // The events of the burst are all received, errors are reported and don't end it
+    let mut report = fmt::Report::default();
+    let first = Ok(Event::new(EventKind::Create(CreateKind::File)).add_path(a.clone()));
+    let paths = crate::watch::debounce(first, &rx, &path, &mut report);
+    assert_eq!(paths.into_iter().collect::<Vec<_>>(), [a.clone(), c]);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, path);
+
+    // Files are skipped while they have the content that was written to them
+    fs::write(&a, "let a=1;").unwrap();
+    let written = HashMap::from([(a.clone(), xxhash_rust::xxh3::xxh3_64(b"let a=1;"))]);
+    assert!(crate::watch::is_written(&written, &a));
+    fs::write(&a, "let a=2;").unwrap();
+    assert!(!crate::watch::is_written(&written, &a));
+    assert!(!crate::watch::is_written(&written, &path.join("b.js")));
+}
+
+#[test]
+fn test_filter() {
+    let path = testdir!();
+    for name in [
+        "a.js",
+        "lib.min.js",
+        "vendor/b.js",
+        "src/vendor/c.css",
+        "src/d.css",
+    ] {
+        let file = path.join(name);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        File::create(file).unwrap();
+    }
+    let mut config = Config {
+        ignore_path: vec!["**/vendor/**".into(), "*.min.js".into()],
+        ..Default::default()
+    };
+    let mut paths = Vec::new();
+    files::recurse_dir(&path, &config, &mut paths).unwrap();
+    paths.sort();
+    assert_eq!(paths, [path.join("a.js"), path.join("src/d.css")]);
+
+    config.include_path = vec!["src/".into()];
+    let filter = files::Filter::new(&path, &config).unwrap();
+    assert!(filter.allows(&path.join("src/d.css")));
+    assert!(!filter.allows(&path.join("a.js")));
+    assert!(!filter.allows(&path.join("src/vendor/c.css")));
+}
+
+#[test]
+fn test_ignore_files() {
+    let path = testdir!();
+    for name in [
+        "a.js",
+        ".git/b.js",
+        "node_modules/c.js",
+        "build/d.js",
+        "src/e.css",
+        "src/f.js",
+    ] {
+        let file = path.join(name);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        File::create(file).unwrap();
+    }
+    fs::write(path.join(".gitignore"), "node_modules/\n").unwrap();
+    fs::write(path.join(".ignore"), "build/\n").unwrap();
+    fs::write(path.join("src/.wwwfmtignore"), "*.css\n").unwrap();
+    let mut config = Config::default();
+    let mut paths = Vec::new();
+    files::recurse_dir(&path, &config, &mut paths).unwrap();
+    assert_eq!(paths, [path.join("a.js"), path.join("src/f.js")]);
+
+    config.respect_ignore_files = false;
+    let mut paths = Vec::new();
+    files::recurse_dir(&path, &config, &mut paths).unwrap();
+    assert_eq!(paths.len(), 6);
+}
+
+#[test]
+fn test_html5() {
+    let alloc = Allocator::new();
+    let config = Config::default();
+    let html = Html::new(
+        "<ul><li>a<li>b</ul><p class=x>1 < 2<table><tr><td>c</td></tr>d</table>",
+        &alloc,
+        &config,
+    );
+    let ugly = html
+        .minify()
+        .unwrap_or_else(|e| panic!("Minify failed: {e}"));
+    assert_eq!(
+        core::str::from_utf8(&ugly).unwrap(),
+        "<ul><li>a</li><li>b</li></ul><p class=\"x\">1 &lt; 2</p>d<table><tbody><tr><td>c</td></tr></tbody></table>"
+    );
+
+    // Partials are parsed as fragments, without implied html, head and body elements
+    for (src, expected) in [
+        (
+            "<tr><td>x</td><th>y</th></tr>",
+            "<tr>\n  <td>\n    x\n  </td>\n  <th>\n    y\n  </th>\n</tr>\n",
+        ),
+        (
+            "<li>item</li><li><a href=\"a\">b</a></li>",
+            "<li>\n  item\n</li>\n<li>\n  <a href=\"a\">b</a>\n</li>\n",
+        ),
+    ] {
+        let min =
+            fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+        assert_eq!(min, src);
+        let pretty =
+            fmt::format_str(src, Language::Html, &config, Mode::Prettify).expect("Prettify failed");
+        assert_eq!(pretty, expected);
+        let again = fmt::format_str(&pretty, Language::Html, &config, Mode::Prettify)
+            .expect("Prettify failed");
+        assert_eq!(again, expected);
+    }
+
+    // The content of plaintext runs to the end of the document, even when preserved
+    let mut config = Config::default();
+    config.html.preserve_tags = vec!["div".into()];
+    for src in [
+        "<p>a<plaintext> <b>x</b></p>\n",
+        "<div><plaintext> <b>x</b></p>\n",
+    ] {
+        for mode in [Mode::Minify, Mode::Prettify] {
+            let out = fmt::format_str(src, Language::Html, &config, mode).expect("Format failed");
+            assert!(out.ends_with("<plaintext> <b>x</b></p>\n"), "{out}");
+        }
+    }
+}
+
+#[test]
+fn test_html_attrs() {
+    let mut config = Config::default();
+    let src = "<p onclick=\"if (a) { return  false ; } ;  f( 1 , 2 )\" style=\"color: red ; margin : 0px\" onload=\"a b\">x</p>";
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert_eq!(
+        min,
+        "<p onclick=\"if(a)return!1;f(1,2)\" style=\"color:red;margin:0\" onload=\"a b\">x</p>"
+    );
+    // Only event handlers are formatted as JavaScript
+    let src = "<my-step one=\"f( 1 )\" onboarding-step=\"a ( )\" online=\"b ;\"></my-step>";
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert_eq!(min, src);
+    let src = "<p onclick=\"f( 1 , 2 )\" style=\"color: red ; margin : 0px\">x</p>";
+    let pretty =
+        fmt::format_str(src, Language::Html, &config, Mode::Prettify).expect("Prettify failed");
+    assert!(
+        pretty.contains("<p onclick=\"f(1, 2)\" style=\"color: red; margin: 0\">"),
+        "{pretty}"
+    );
+
+    config.html.fmt_attrs = false;
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert!(min.contains("<p onclick=\"f( 1 , 2 )\" style=\"color: red ; margin : 0px\">"));
+}
+
+#[test]
+fn test_html_attr_minify() {
+    let mut config = Config::default();
+    let src = "<form method=\"GET\"><input type=\"text\" disabled=\"disabled\" class=\" x  y \" value=\"a b\" data-x=\"\"></form>";
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert!(min.contains("<form method=\"GET\"><input type=\"text\" disabled=\"disabled\" class=\" x  y \" value=\"a b\" data-x=\"\">"));
+
+    config.html.uglify_unquote_attrs = true;
+    config.html.uglify_collapse_bool_attrs = true;
+    config.html.uglify_rm_default_attrs = true;
+    config.html.uglify_trim_class = true;
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert!(
+        min.contains("<form><input disabled class=\"x y\" value=\"a b\" data-x></form>"),
+        "{min}"
+    );
+    let pretty =
+        fmt::format_str(src, Language::Html, &config, Mode::Prettify).expect("Prettify failed");
+    assert!(pretty.contains("<form method=\"GET\">"), "{pretty}");
+
+    // Boolean attributes of other elements are values like any other
+    let src =
+        "<my-toggle open=\"open\" hidden=\"hidden\"></my-toggle><details open=\"open\"></details>";
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert_eq!(
+        min,
+        "<my-toggle open=open hidden></my-toggle><details open></details>"
+    );
+}
+
+#[test]
+fn test_omit_tags() {
+    let mut config = Config::default();
+    let src = "<!DOCTYPE html><html><head><title>x</title></head><body><ul><li>a</li><li>b</li></ul><p>c</p><div>d</div><table><tr><td>1</td><td>2</td></tr></table><a><p>e</p></a></body></html>";
+    config.html.uglify_omit_tags = OmitTags::EndTags;
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert_eq!(
+        min,
+        "<!DOCTYPE html><html><head><title>x</title><body><ul><li>a<li>b</ul><p>c<div>d</div><table><tbody><tr><td>1<td>2</table><a><p>e</p></a>"
+    );
+    config.html.uglify_omit_tags = OmitTags::All;
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert!(
+        min.starts_with("<!DOCTYPE html><title>x</title><ul>"),
+        "{min}"
+    );
+
+    // The omitted tags are implied by the parser
+    config.html.uglify_omit_tags = OmitTags::None;
+    let full = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    let reparsed =
+        fmt::format_str(&min, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert_eq!(reparsed, full);
+}
+
+#[test]
+fn test_collapse_whitespace() {
+    let mut config = Config::default();
+    let src = "<div>\n  <p>  Hello   <b>big</b>   world  <br>  next  </p>\n</div>\n<span>a</span> <span>b</span>\n<ul> <li> x </li> </ul>";
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert!(min.contains("<div> <p> Hello <b>big</b> world <br> next </p> </div> <span>a</span>"));
+
+    config.html.uglify_collapse_whitespace = Whitespace::Conservative;
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert_eq!(
+        min,
+        "<div><p>Hello <b>big</b> world <br> next</p></div><span>a</span> <span>b</span><ul><li>x</li></ul>"
+    );
+
+    config.html.uglify_collapse_whitespace = Whitespace::Aggressive;
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert!(
+        min.contains("<p>Hello <b>big</b> world<br>next</p>"),
+        "{min}"
+    );
+    let min = fmt::format_str("<p>a   b</p>", Language::Html, &config, Mode::Minify)
+        .expect("Minify failed");
+    assert!(min.contains("<p>a b</p>"), "{min}");
+}
+
+#[test]
+fn test_preserve() {
+    let mut config = Config::default();
+    config.html.preserve_classes = vec!["code".into()];
+    config.html.preserve_attrs = vec!["data-raw".into()];
+    config.html.uglify_unquote_attrs = true;
+    config.html.uglify_collapse_whitespace = Whitespace::Aggressive;
+    let src = "<div><textarea>  a\n    b  </textarea><p class=\"x code\">  keep   <b style=\"color : red\">  this </b> </p><span data-raw>  a  </span></div>";
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert!(
+        min.contains("<textarea>  a\n    b  </textarea><p class=\"x code\">  keep   <b style=\"color : red\">  this </b> </p><span data-raw=\"\">  a  </span>"),
+        "{min}"
+    );
+    let pretty =
+        fmt::format_str(src, Language::Html, &config, Mode::Prettify).expect("Prettify failed");
+    assert!(
+        pretty.contains("<textarea>  a\n    b  </textarea>\n"),
+        "{pretty}"
+    );
+    assert!(pretty.contains(">  keep   <b style=\"color : red\">  this </b> </p>"));
+
+    // The content is written as it is in the source
+    let src = "<pre>a &#60; &copy;<b class='x'>b</b></pre><pre\n  class=\"y\">\n\n<i>c</i>\n</pre><textarea>&lt;d&gt;</textarea>";
+    for mode in [Mode::Minify, Mode::Prettify] {
+        let out = fmt::format_str(src, Language::Html, &config, mode).expect("Format failed");
+        assert!(
+            out.contains(">a &#60; &copy;<b class='x'>b</b></pre>"),
+            "{out}"
+        );
+        assert!(out.contains(">\n\n<i>c</i>\n</pre>"), "{out}");
+        assert!(out.contains(">&lt;d&gt;</textarea>"), "{out}");
+    }
+    // Content closed by other elements is written from the tree, which gives the same tree
+    let src = "<p class=\"code\">a <div>b</div></p>";
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert!(min.contains("<p class=code>a </p><div>b</div>"), "{min}");
+    let again =
+        fmt::format_str(&min, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert_eq!(min, again);
+
+    // Preserved scripts and styles are not formatted
+    config.html.preserve_tags = vec!["script".into(), "STYLE".into()];
+    let src = "<script>let  a = 1;</script><style>a { color : red }</style>";
+    for mode in [Mode::Minify, Mode::Prettify] {
+        let out = fmt::format_str(src, Language::Html, &config, mode).expect("Format failed");
+        assert!(out.contains("<script>let  a = 1;</script>"), "{out}");
+        assert!(out.contains("<style>a { color : red }</style>"), "{out}");
+    }
+}
+
+#[test]
+fn test_script_types() {
+    let config = Config::default();
+    let src = "<script>let  a = 1;</script><script type=\"module\">import a from 'a';</script><script type=\"application/ld+json\">{ \"a\": [1, 2e3] }</script><script type=\"text/x-template\">  <b>  x </b>\n</script>";
+    let min = fmt::format_str(src, Language::Html, &config, Mode::Minify).expect("Minify failed");
+    assert!(min.contains("<script>let a=1;</script>"), "{min}");
+    assert!(
+        min.contains("<script type=\"module\">import a from\"a\";</script>"),
+        "{min}"
+    );
+    assert!(min.contains(">{\"a\":[1,2e3]}</script>"), "{min}");
+    assert!(min.contains(">  <b>  x </b>\n</script>"), "{min}");
+    let pretty =
+        fmt::format_str(src, Language::Html, &config, Mode::Prettify).expect("Prettify failed");
+    assert!(
+        pretty.contains("{\n    \"a\": [\n      1,\n      2e3\n    ]\n  }\n"),
+        "{pretty}"
+    );
+    assert!(pretty.contains(">  <b>  x </b>\n</script>"), "{pretty}");
+    // The type of scripts without one is given by their language, untyped scripts that are not
+    // JavaScript are kept as they are
+    let src = "<script language=\"vbscript\">  MsgBox  \"a\"</script><script language=\"JavaScript\">let  a = 1;</script><script>  {{ a }} = 1</script>";
+    for mode in [Mode::Minify, Mode::Prettify] {
+        let out = fmt::format_str(src, Language::Html, &config, mode).expect("Format failed");
+        assert!(out.contains(">  MsgBox  \"a\"</script>"), "{out}");
+        assert!(out.contains("let a"), "{out}");
+        assert!(out.contains("<script>  {{ a }} = 1</script>"), "{out}");
+    }
+    let e = fmt::format_str(
+        "<script type=\"importmap\">{\"a\": }</script>",
+        Language::Html,
+        &config,
+        Mode::Minify,
+    )
+    .expect_err("Invalid JSON formatted");
+    assert!(matches!(e, Error::Json { .. }));
+    assert_eq!(e.span().map(|s| s.col), Some(32));
+}
+
+#[test]
+fn test_script_escape() {
+    let config = Config::default();
+    let src = "<script>var s = \"<\\/script>\"; var t = '</scr' + 'ipt>'; var c = '<!--';</script><p>x</p>";
+    for mode in [Mode::Minify, Mode::Prettify] {
+        let out = fmt::format_str(src, Language::Html, &config, mode).expect("Format failed");
+        assert_eq!(out.matches("</script>").count(), 1, "{out}");
+        assert!(!out.contains("<!--"), "{out}");
+        assert!(out.contains("<\\/script>"), "{out}");
+        // The escaped script is formatted to the same output
+        let again = fmt::format_str(&out, Language::Html, &config, mode).expect("Format failed");
+        assert_eq!(out, again);
+    }
+}
+
+#[test]
+fn test_check() {
+    let path = testdir!();
+    copy_files_to(path.clone());
+    let config = Config::default();
+    let file = path.join("example.js");
+    assert!(fmt::check_file(&file, None, &config, true, false, None).expect("Check failed"));
+    fmt::file(&file, None, &config, true, false, None).expect("Minify failed");
+    assert!(!fmt::check_file(&file, None, &config, true, false, None).expect("Check failed"));
+    assert!(fmt::check_file(&file, None, &config, true, true, None).expect("Check failed"));
+
+    // Every file is checked, even after an error
+    let path = testdir!().join("project");
+    fs::create_dir(&path).unwrap();
+    fs::write(path.join("a.css"), "}} {").unwrap();
+    fs::write(path.join("b.js"), "let  b = 1;\n").unwrap();
+    fs::write(path.join("c.css"), "c{color:red}").unwrap();
+    fs::write(path.join("d.txt"), "d").unwrap();
+    let report = fmt::check(&path, &config, true, true).expect("Check failed");
+    assert_eq!(report.formatted, [path.join("b.js")]);
+    assert_eq!(report.unchanged, [path.join("c.css")]);
+    assert_eq!(report.skipped, [path.join("d.txt")]);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, path.join("a.css"));
+}
+
+#[test]
+fn test_diff() {
+    let path = testdir!();
+    copy_files_to(path.clone());
+    let config = Config::default();
+    let file = path.join("example.ts");
+    let diff = fmt::diff_file(&file, &config, false, None)
+        .expect("Diff failed")
+        .expect("File should change");
+    assert!(diff.starts_with(&format!(
+        "--- {}\n+++ {}\n@@",
+        file.display(),
+        file.display()
+    )));
+    fmt::file(&file, None, &config, false, true, None).expect("Prettify failed");
+    assert_eq!(
+        fmt::diff_file(&file, &config, false, None).expect("Diff failed"),
+        None
+    );
+
+    // Every file is diffed, even after an error, with the link to its source map
+    let path = testdir!().join("project");
+    fs::create_dir(&path).unwrap();
+    fs::write(path.join("a.css"), "}} {").unwrap();
+    fs::write(path.join("b.js"), "let  b = 1;\n").unwrap();
+    let mut config = Config::default();
+    config.javascript.uglify_source_map = SourceMap::Linked;
+    let diffs = fmt::diff(&path, &config, true).expect("Diff failed");
+    assert_eq!(diffs.len(), 2);
+    assert_eq!(diffs[0].0, path.join("a.css"));
+    assert!(diffs[0].1.is_err());
+    let diff = diffs[1].1.as_ref().expect("Diff failed");
+    assert!(diff.contains("\n+//# sourceMappingURL=b.js.map"), "{diff}");
+}
+
+#[test]
+fn test_format_str() {
+    let config = Config::default();
+    let css = fmt::format_str(
+        "a {\n  color : red ;\n}\n",
+        Language::Css,
+        &config,
+        Mode::Minify,
+    )
+    .expect("CSS minify failed");
+    assert_eq!(css, "a{color:red}");
+    let tsx = fmt::format_str(
+        get_file("example.tsx"),
+        Language::from_extension("tsx").unwrap(),
+        &config,
+        Mode::Prettify,
+    )
+    .expect("TSX prettify failed");
+    let file = testdir!().join("example.tsx");
+    File::create_new(&file)
+        .unwrap()
+        .write_all(get_file("example.tsx").as_bytes())
+        .unwrap();
+    fmt::file(&file, None, &config, false, true, None).expect("TSX prettify failed");
+    assert_eq!(tsx, std::fs::read_to_string(file).unwrap());
+}
+
+#[test]
+fn test_json() {
+    let mut config = Config::default();
+    let src = "{ \"b\": [1, 2.50], // b\n  /* a */ \"a\": {} , }";
+    let pretty = fmt::format_str(src, Language::Jsonc, &config, Mode::Prettify)
+        .expect("JSONC prettify failed");
+    assert_eq!(
+        pretty,
+        "{\n  \"b\": [\n    1,\n    2.50\n  ], // b\n  /* a */\n  \"a\": {}\n}\n"
+    );
+    let min =
+        fmt::format_str(src, Language::Jsonc, &config, Mode::Minify).expect("JSONC minify failed");
+    assert_eq!(min, "{\"b\":[1,2.50],\"a\":{}}");
+    assert!(fmt::format_str(src, Language::Json, &config, Mode::Minify).is_err());
+
+    config.json.sort_keys = true;
+    config.json.uglify_rm_comments = false;
+    let min =
+        fmt::format_str(src, Language::Jsonc, &config, Mode::Minify).expect("JSONC minify failed");
+    assert_eq!(min, "{/* a */\"a\":{},\"b\":[1,2.50]// b\n}");
+
+    // Source maps next to their file are not formatted with the project
+    let path = testdir!();
+    copy_files_to(path.clone());
+    fs::write(path.join("example.js.map"), "{ \"version\": 3 }").unwrap();
+    fs::write(path.join("manifest.webmanifest"), "{ \"name\": \"a\" }").unwrap();
+    let report = fmt::all(path.clone(), &config, true, true, 0, false).expect("Minify failed");
+    assert!(report.skipped.contains(&path.join("example.js.map")));
+    assert_eq!(
+        fs::read_to_string(path.join("manifest.webmanifest")).unwrap(),
+        "{\"name\":\"a\"}"
+    );
+
+    // A leading byte order mark is dropped
+    let min = fmt::format_str(
+        "\u{FEFF}{ \"a\": 1 }",
+        Language::Json,
+        &config,
+        Mode::Minify,
+    )
+    .expect("JSON with BOM minify failed");
+    assert_eq!(min, "{\"a\":1}");
+
+    // Deeply nested sources are rejected instead of overflowing the stack
+    let src = format!("{}{}", "[".repeat(128), "]".repeat(128));
+    let min = fmt::format_str(&src, Language::Json, &config, Mode::Minify).expect("Minify failed");
+    assert_eq!(min, src);
+    let src = "[{\"a\":".repeat(100_000);
+    let e = fmt::format_str(&src, Language::Json, &config, Mode::Minify).unwrap_err();
+    assert_eq!(e.msg(), "Too deeply nested");
+}
+
+#[test]
+fn test_xml() {
+    let mut config = Config::default();
+    let src = "<?xml version=\"1.0\"?>\n<!-- c -->\n<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:inkscape=\"http://www.inkscape.org/namespaces/inkscape\" width=\"10.50\">\n  <metadata>m</metadata>\n  <style>.a { fill : red }</style>\n  <g stroke=\"blue\" inkscape:label=\"l\">\n    <path stroke=\"none\" opacity=\"1\" d=\"M 0.5 1.0 L 2 -3.25 L 4 4\"/>\n    <text>a <tspan>b</tspan></text>\n  </g>\n</svg>\n";
+    let min =
+        fmt::format_str(src, Language::Svg, &config, Mode::Minify).expect("SVG minify failed");
+    assert_eq!(
+        min,
+        "<?xml version=\"1.0\"?><svg xmlns=\"http://www.w3.org/2000/svg\" width=\"10.5\"><style>.a{fill:red}</style><g stroke=\"blue\"><path stroke=\"none\" d=\"M.5 1 2-3.25 4 4\"/><text>a <tspan>b</tspan></text></g></svg>"
+    );
+    config.xml.uglify_svg_precision = Some(1);
+    let min =
+        fmt::format_str(src, Language::Svg, &config, Mode::Minify).expect("SVG minify failed");
+    assert!(min.contains("d=\"M.5 1 2-3.2 4 4\""), "{min}");
+    let pretty =
+        fmt::format_str(src, Language::Svg, &config, Mode::Prettify).expect("SVG prettify failed");
+    assert!(pretty.starts_with("<?xml version=\"1.0\"?>\n<!-- c -->\n<svg"));
+    assert!(pretty.contains("\n  <style>\n    .a {\n      fill: red;\n    }\n  </style>\n"));
+    assert!(pretty.contains("\n    <text>a <tspan>b</tspan></text>\n"));
+
+    // Whitespace between text spans and in preserved content is kept
+    let src = "<svg>\n  <text>\n    <tspan>a</tspan> <tspan>b</tspan>\n  </text>\n  <g xml:space=\"preserve\">\n    <desc> c  d </desc>\n  </g>\n</svg>\n";
+    for mode in [Mode::Minify, Mode::Prettify] {
+        let out = fmt::format_str(src, Language::Svg, &config, mode).expect("SVG format failed");
+        assert!(out.contains("<tspan>a</tspan> <tspan>b</tspan>"), "{out}");
+        assert!(
+            out.contains("<g xml:space=\"preserve\">\n    <desc> c  d </desc>\n  </g>"),
+            "{out}"
+        );
+    }
+
+    let e = fmt::format_str("<a>\n<b></a>", Language::Xml, &config, Mode::Minify)
+        .expect_err("Invalid XML formatted");
+    assert!(matches!(e, Error::Xml { .. }));
+    assert_eq!(e.span().map(|s| s.line), Some(2));
+}
+
+#[test]
+fn test_targets() {
+    let config = Config {
+        targets: Some("safari 12".into()),
+        ..Default::default()
+    };
+    let css = fmt::format_str(
+        ".a {\n  .b { user-select: none; }\n}\n",
+        Language::Css,
+        &config,
+        Mode::Minify,
+    )
+    .expect("CSS minify failed");
+    assert_eq!(css, ".a .b{-webkit-user-select:none;user-select:none}");
+    let js = fmt::format_str("f(a ?? b);\n", Language::JavaScript, &config, Mode::Minify)
+        .expect("JS minify failed");
+    assert!(!js.contains("??"), "{js}");
+    let js = fmt::format_str(
+        "f(a ?? b);\n",
+        Language::JavaScript,
+        &config,
+        Mode::Prettify,
+    )
+    .expect("JS prettify failed");
+    assert_eq!(js, "f(a ?? b);\n");
+
+    let config = Config {
+        targets: Some("ie 11".into()),
+        ..Default::default()
+    };
+    let js = fmt::format_str(
+        "const f = async () => {\n  let { a, ...b } = o ?? {};\n  return a?.c(b);\n};\nclass A {\n  #x = 1;\n}\n",
+        Language::JavaScript,
+        &config,
+        Mode::Minify,
+    )
+    .expect("JS minify failed");
+    for syntax in ["=>", "async(", "...", "??", "?.", "#x"] {
+        assert!(!js.contains(syntax), "{syntax} not lowered: {js}");
+    }
+    let e = fmt::format_str(
+        "let a: number = 1;\n",
+        Language::TypeScript,
+        &config,
+        Mode::Minify,
+    )
+    .unwrap_err();
+    assert!(e.msg().contains("uglify_transpile"), "{e}");
+
+    let config = Config {
+        targets: Some("not a browser".into()),
+        ..Default::default()
+    };
+    let e = fmt::format_str("a{}", Language::Css, &config, Mode::Minify).unwrap_err();
+    assert!(e.msg().starts_with("Invalid targets"), "{e}");
+}
+
+#[test]
+fn test_transpile() {
+    let path = testdir!();
+    fs::write(
+        path.join("app.tsx"),
+        "const n: number = 1;\nexport const A = () => <div>{n}</div>;\n",
+    )
+    .unwrap();
+    fs::write(path.join("lib.mts"), "export type T = string;\n").unwrap();
+    let mut config = Config::default();
+    config.javascript.uglify_transpile = true;
+    config.javascript.uglify_jsx_runtime = JsxRuntime::Classic;
+    config.javascript.uglify_jsx_pragma = Some("h".into());
+    let report = fmt::all(path.clone(), &config, true, false, 0, false).expect("Minify failed");
+    assert!(report.is_ok(), "Minify failed: {:?}", report.failed);
+    let js = fs::read_to_string(path.join("wwwugly/app.js")).unwrap();
+    assert!(js.contains("h(`div`,null,"), "{js}");
+    assert!(!js.contains("number"), "{js}");
+    assert!(path.join("wwwugly/lib.mjs").exists());
+
+    let e = fmt::file(path.join("app.tsx"), None, &config, true, true, None).unwrap_err();
+    assert_eq!(e.msg(), "Transpiled files can't be written in place");
+    // Prettifying keeps TypeScript as is
+    let ts = fmt::format_str(
+        "let a: number = 1;\n",
+        Language::TypeScript,
+        &config,
+        Mode::Prettify,
+    )
+    .expect("TS prettify failed");
+    assert_eq!(ts, "let a: number = 1;\n");
+}
+
+#[test]
+fn test_error() {
+    let config = Config::default();
+    let e = fmt::format_str(
+        "const a = 1;\nlet b = ;\n",
+        Language::JavaScript,
+        &config,
+        Mode::Minify,
+    )
+    .expect_err("Invalid JavaScript was minified");
+    assert!(matches!(e, Error::JavaScript { .. }));
+    assert_eq!(
+        e.span(),
+        Some(Span {
+            line: 2,
+            col: 9,
+            len: 1
+        })
+    );
+    let e = fmt::format_str("a {}\n}} @@ {", Language::Css, &config, Mode::Minify)
+        .expect_err("Invalid CSS was minified");
+    assert!(matches!(e, Error::Css { .. }));
+    assert_eq!(e.span().map(|s| s.line), Some(2));
+}
+
+#[test]
+fn test_report() {
+    let alloc = Allocator::new();
+    let config = Config::default();
+    let src = "<!DOCTYPE html>\n<p>text</p><script type=\"module\">let a =\n  1 +;</script>\n";
+    let e = Html::new(src, &alloc, &config)
+        .minify()
+        .expect_err("Invalid JavaScript was minified");
+    assert_eq!(
+        e.report(src),
+        "error: Unexpected token\n --> <source>:3:6\n  |\n3 |   1 +;</script>\n  |      ^\n"
+    );
+    let src = "<p>text</p><style>}} {}</style>";
+    let e = Html::new(src, &alloc, &config)
+        .minify()
+        .expect_err("Invalid CSS was minified");
+    assert_eq!(e.span().map(|s| (s.line, s.col)), Some((1, 19)));
 }
@@ -0,0 +1,160 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+use notify::{Event, EventKind, RecursiveMode, Watcher, event::ModifyKind};
+use oxc::allocator::Allocator;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    files,
+    fmt::{self, Report},
+};
+
+/// How long to wait for more events before formatting the changed files
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Adds the files created or modified by an event to `paths`
+fn collect(event: notify::Result<Event>, root: &Path, paths: &mut BTreeSet<PathBuf>) -> Result<()> {
+    let event = event
+        .map_err(|e| Error::io(Some(root), "Failed to watch directory", io::Error::other(e)))?;
+    match event.kind {
+        EventKind::Create(_) => paths.extend(event.paths),
+        EventKind::Modify(kind) if !matches!(kind, ModifyKind::Metadata(_)) => {
+            paths.extend(event.paths)
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Receives a burst of events, from `first` until none is received for [`DEBOUNCE`], and returns
+/// the files they created or modified. Errors are added to the failures of `report`.
+pub fn debounce(
+    first: notify::Result<Event>,
+    rx: &Receiver<notify::Result<Event>>,
+    root: &Path,
+    report: &mut Report,
+) -> BTreeSet<PathBuf> {
+    let mut paths = BTreeSet::new();
+    let mut event = first;
+    loop {
+        if let Err(e) = collect(event, root, &mut paths) {
+            report.failed.push((root.to_path_buf(), e));
+        }
+        match rx.recv_timeout(DEBOUNCE) {
+            Ok(next) => event = next,
+            Err(_) => return paths,
+        }
+    }
+}
+
+/// Returns whether a file still has the content written when formatting it in place (or its
+/// source map), given the hashes of the written files
+pub fn is_written(written: &HashMap<PathBuf, u64>, path: &Path) -> bool {
+    written
+        .get(path)
+        .is_some_and(|hash| fs::read(path).is_ok_and(|data| xxh3_64(&data) == *hash))
+}
+
+/// Formats all files starting from the project's root directory, then watches the project and
+/// formats again the files that are created or modified.
+///
+/// Takes the same arguments as [`fmt::all`], the cache is used only by the first run.
+/// `on_report` is called with the [`Report`] of the first run, and then with the report of every
+/// burst of changes.
+///
+/// Changes in the output directories are ignored, and so are the ones caused by formatting files
+/// in place. This function only returns if the first run fails or the project can't be watched:
+/// later errors are reported as failures of the root directory, and the project is still watched.
+pub fn watch<P: Into<PathBuf>, F: FnMut(Report)>(
+    root: P,
+    config: &Config,
+    minify: bool,
+    inplace: bool,
+    jobs: usize,
+    cache: bool,
+    mut on_report: F,
+) -> Result<()> {
+    let root: PathBuf = root.into();
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(|e| {
+        Error::io(
+            Some(&root),
+            "Failed to watch directory",
+            io::Error::other(e),
+        )
+    })?;
+    let report = fmt::all(root.clone(), config, minify, inplace, jobs, cache)?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(|e| {
+            Error::io(
+                Some(&root),
+                "Failed to watch directory",
+                io::Error::other(e),
+            )
+        })?;
+    // The changes made once the first run is reported are seen
+    on_report(report);
+
+    let outdirs: Vec<PathBuf> = [&config.uglify_outdir, &config.prettify_outdir]
+        .into_iter()
+        .flatten()
+        .map(|dir| root.join(dir))
+        .collect();
+    // Files of the project, honoring the ignore rules
+    let mut known = HashSet::new();
+    // Hashes of the files formatted in place, to recognize our own writes
+    let mut written = HashMap::new();
+    let mut alloc = Allocator::new();
+    let opt_root = Some(root.clone());
+    while let Ok(event) = rx.recv() {
+        let mut report = Report::default();
+        let mut paths = debounce(event, &rx, &root, &mut report);
+        paths.retain(|p| p.is_file() && !outdirs.iter().any(|dir| p.starts_with(dir)));
+        // New files need to be checked against the ignore rules
+        if paths.iter().any(|p| !known.contains(p)) {
+            let mut files = Vec::new();
+            match files::recurse_dir(&root, config, &mut files) {
+                Ok(()) => known = files.into_iter().collect(),
+                Err(e) => report.failed.push((root.clone(), e)),
+            }
+        }
+
+        for path in paths.into_iter().filter(|p| known.contains(p)) {
+            let Some(ext) = fmt::project_ext(&path) else {
+                continue;
+            };
+            if is_written(&written, &path) {
+                continue;
+            }
+            match fmt::inner_file(&path, &opt_root, ext, config, minify, inplace, &alloc) {
+                Ok(Some(fmted)) => {
+                    if inplace {
+                        written.insert(path.clone(), xxh3_64(&fmted));
+                        // The source map written next to the file
+                        let map = files::map_path(&path);
+                        if let Ok(data) = fs::read(&map) {
+                            written.insert(map, xxh3_64(&data));
+                        }
+                    }
+                    report.formatted.push(path);
+                }
+                Ok(None) => report.skipped.push(path),
+                Err(e) => report.failed.push((path, e)),
+            }
+            alloc.reset();
+        }
+        if !report.formatted.is_empty() || !report.failed.is_empty() {
+            on_report(report);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,683 @@
+use std::{borrow::Cow, collections::HashSet, fmt::Display};
+
+use quick_xml::{Reader, escape::unescape, events::Event};
+
+use crate::{
+    config::Config,
+    css,
+    error::{Error, Result, Span},
+    html::{indent, write_lines},
+};
+
+/// Namespaces of the metadata written by editors (Inkscape, Sketch, Illustrator, ...)
+const EDITOR_NS: [&str; 14] = [
+    "http://www.inkscape.org/namespaces/inkscape",
+    "http://sodipodi.sourceforge.net/DTD/sodipodi-0.dtd",
+    "http://www.bohemiancoding.com/sketch/ns",
+    "http://www.serif.com/",
+    "http://ns.adobe.com/AdobeIllustrator/10.0/",
+    "http://ns.adobe.com/AdobeSVGViewerExtensions/3.0/",
+    "http://ns.adobe.com/Extensibility/1.0/",
+    "http://ns.adobe.com/Flows/1.0/",
+    "http://ns.adobe.com/GenericCustomNamespace/1.0/",
+    "http://ns.adobe.com/Graphs/1.0/",
+    "http://ns.adobe.com/ImageReplacement/1.0/",
+    "http://ns.adobe.com/SaveForWeb/1.0/",
+    "http://ns.adobe.com/Variables/1.0/",
+    "http://ns.adobe.com/XPath/1.0/",
+];
+
+/// SVG elements whose content is text, where the whitespace between child elements is significant
+const TEXT_ELEMENTS: [&str; 5] = ["text", "tspan", "textPath", "title", "desc"];
+
+/// Attributes that are not inherited, with their default value on an element
+const DEFAULT_ATTRS: [(&str, &str, &str); 25] = [
+    ("svg", "x", "0"),
+    ("svg", "y", "0"),
+    ("svg", "preserveAspectRatio", "xMidYMid meet"),
+    ("symbol", "preserveAspectRatio", "xMidYMid meet"),
+    ("image", "preserveAspectRatio", "xMidYMid meet"),
+    ("marker", "preserveAspectRatio", "xMidYMid meet"),
+    ("pattern", "preserveAspectRatio", "xMidYMid meet"),
+    ("rect", "x", "0"),
+    ("rect", "y", "0"),
+    ("image", "x", "0"),
+    ("image", "y", "0"),
+    ("use", "x", "0"),
+    ("use", "y", "0"),
+    ("circle", "cx", "0"),
+    ("circle", "cy", "0"),
+    ("ellipse", "cx", "0"),
+    ("ellipse", "cy", "0"),
+    ("line", "x1", "0"),
+    ("line", "y1", "0"),
+    ("line", "x2", "0"),
+    ("line", "y2", "0"),
+    ("linearGradient", "spreadMethod", "pad"),
+    ("radialGradient", "spreadMethod", "pad"),
+    ("linearGradient", "gradientUnits", "objectBoundingBox"),
+    ("radialGradient", "gradientUnits", "objectBoundingBox"),
+];
+
+/// Inherited presentation attributes with their initial value. An element can override the value
+/// set by an ancestor, so they are removed only where nothing else can set them
+const INHERITED_DEFAULTS: [(&str, &str); 12] = [
+    ("fill-opacity", "1"),
+    ("fill-rule", "nonzero"),
+    ("clip-rule", "nonzero"),
+    ("stroke", "none"),
+    ("stroke-opacity", "1"),
+    ("stroke-width", "1"),
+    ("stroke-linecap", "butt"),
+    ("stroke-linejoin", "miter"),
+    ("stroke-miterlimit", "4"),
+    ("stroke-dasharray", "none"),
+    ("stroke-dashoffset", "0"),
+    ("visibility", "visible"),
+];
+
+/// Attributes whose value is a list of numbers
+const NUMERIC_ATTRS: [&str; 20] = [
+    "x",
+    "y",
+    "x1",
+    "y1",
+    "x2",
+    "y2",
+    "cx",
+    "cy",
+    "fx",
+    "fy",
+    "r",
+    "rx",
+    "ry",
+    "width",
+    "height",
+    "viewBox",
+    "points",
+    "opacity",
+    "stroke-width",
+    "offset",
+];
+
+/// A node of an XML document
+enum Node {
+    Element(Element),
+    /// Text, escaped as in the source
+    Text(String),
+    CData(String),
+    Comment(String),
+    /// Declaration, processing instruction or doctype, written as it is
+    Other(String),
+}
+
+struct Element {
+    name: String,
+    /// Attributes with their value, escaped as in the source
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns whether the whitespace of the content is significant (mixed content, CDATA, or
+    /// `xml:space="preserve"`)
+    fn is_inline(&self) -> bool {
+        self.attr("xml:space") == Some("preserve")
+            || self.children.iter().any(|c| match c {
+                Node::Text(text) => !text.trim().is_empty(),
+                Node::CData(_) => true,
+                _ => false,
+            })
+    }
+}
+
+/// Formats XML, or SVG if `svg` (its `style` elements are formatted as CSS, and it's optimized
+/// when minifying if enabled)
+pub fn fmt_str(src: &str, config: &Config, minify: bool, svg: bool) -> Result<String> {
+    let mut nodes = parse(src)?;
+    if svg && minify && config.xml.uglify_svg_optimize {
+        optimize(&mut nodes, config);
+    }
+    let mut xml = Xml {
+        config,
+        out: String::with_capacity(src.len()),
+        minify,
+        svg,
+        indent: 0,
+    };
+    for node in &nodes {
+        xml.write_node(node, false)?;
+    }
+    Ok(xml.out)
+}
+
+fn error(src: &str, pos: u64, msg: impl Display) -> Error {
+    Error::Xml {
+        path: None,
+        span: Some(Span::from_offset(src, pos as usize)),
+        msg: format!("Invalid XML: {msg}"),
+        help: None,
+    }
+}
+
+/// Parses a document into a tree
+fn parse(src: &str) -> Result<Vec<Node>> {
+    let mut reader = Reader::from_str(src);
+    let mut stack: Vec<Element> = Vec::new();
+    let mut nodes = Vec::new();
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| error(src, reader.error_position(), e))?;
+        let node = match &event {
+            Event::Start(start) | Event::Empty(start) => {
+                let mut attrs = Vec::new();
+                for attr in start.attributes() {
+                    let attr = attr.map_err(|e| error(src, reader.buffer_position(), e))?;
+                    attrs.push((
+                        String::from_utf8_lossy(attr.key.as_ref()).into_owned(),
+                        String::from_utf8_lossy(&attr.value).into_owned(),
+                    ));
+                }
+                let element = Element {
+                    name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+                    attrs,
+                    children: Vec::new(),
+                };
+                if matches!(event, Event::Start(_)) {
+                    stack.push(element);
+                    continue;
+                }
+                Node::Element(element)
+            }
+            Event::End(_) => Node::Element(stack.pop().expect("End without start")),
+            Event::Text(text) => Node::Text(String::from_utf8_lossy(text).into_owned()),
+            Event::CData(data) => Node::CData(String::from_utf8_lossy(data).into_owned()),
+            Event::Comment(text) => Node::Comment(String::from_utf8_lossy(text).into_owned()),
+            Event::Decl(decl) => Node::Other(format!("<?{}?>", String::from_utf8_lossy(decl))),
+            Event::PI(pi) => Node::Other(format!("<?{}?>", String::from_utf8_lossy(pi))),
+            Event::DocType(doctype) => Node::Other(format!(
+                "<!DOCTYPE {}>",
+                String::from_utf8_lossy(doctype).trim()
+            )),
+            Event::Eof => break,
+        };
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => nodes.push(node),
+        }
+    }
+    if let Some(element) = stack.last() {
+        return Err(error(
+            src,
+            src.len() as u64,
+            format!("`{}` is not closed", element.name),
+        ));
+    }
+    Ok(nodes)
+}
+
+/// Returns the namespace prefix of a name
+fn prefix(name: &str) -> Option<&str> {
+    name.split_once(':').map(|(prefix, _)| prefix)
+}
+
+/// Returns whether two values are the same, as strings or as numbers
+fn same_value(value: &str, default: &str) -> bool {
+    let value = value.trim();
+    value == default
+        || value
+            .parse::<f64>()
+            .is_ok_and(|v| default.parse::<f64>().is_ok_and(|d| v == d))
+}
+
+/// Optimizes an SVG document
+fn optimize(nodes: &mut Vec<Node>, config: &Config) {
+    let mut editor = HashSet::new();
+    let mut overridable = false;
+    visit(nodes, &mut |elem| {
+        for (key, value) in &elem.attrs {
+            if let Some(prefix) = key.strip_prefix("xmlns:")
+                && EDITOR_NS.contains(&value.as_str())
+            {
+                editor.insert(prefix.to_owned());
+            }
+        }
+        // Stylesheets and `use` can give inherited values to any element
+        overridable |= matches!(elem.name.as_str(), "style" | "use");
+    });
+    let mut optimizer = Optimizer {
+        config,
+        editor,
+        overridable,
+        ancestors: Vec::new(),
+    };
+    optimizer.optimize(nodes);
+    let mut used = HashSet::new();
+    visit(nodes, &mut |elem| {
+        used.extend(prefix(&elem.name).map(|p| p.to_owned()));
+        for (key, _) in &elem.attrs {
+            if !key.starts_with("xmlns") {
+                used.extend(prefix(key).map(|p| p.to_owned()));
+            }
+        }
+    });
+    visit_mut(nodes, &mut |elem| {
+        elem.attrs.retain(|(key, _)| {
+            key.strip_prefix("xmlns:")
+                .is_none_or(|prefix| used.contains(prefix))
+        })
+    });
+}
+
+/// Calls `f` on every element of the tree
+fn visit(nodes: &[Node], f: &mut impl FnMut(&Element)) {
+    for node in nodes {
+        if let Node::Element(elem) = node {
+            f(elem);
+            visit(&elem.children, f);
+        }
+    }
+}
+
+/// Calls `f` on every element of the tree, allowing to change it
+fn visit_mut(nodes: &mut [Node], f: &mut impl FnMut(&mut Element)) {
+    for node in nodes {
+        if let Node::Element(elem) = node {
+            f(elem);
+            visit_mut(&mut elem.children, f);
+        }
+    }
+}
+
+/// Removes what doesn't change how an SVG renders, and shortens the rest
+struct Optimizer<'a> {
+    config: &'a Config,
+    /// Prefixes of the editors' namespaces
+    editor: HashSet<String>,
+    /// Whether inherited values can be set by stylesheets or `use` elements
+    overridable: bool,
+    /// Attributes of the ancestors of the current element
+    ancestors: Vec<HashSet<String>>,
+}
+
+impl Optimizer<'_> {
+    fn is_editor(&self, name: &str) -> bool {
+        let prefix = match name.strip_prefix("xmlns:") {
+            Some(prefix) => Some(prefix),
+            None => prefix(name),
+        };
+        prefix.is_some_and(|p| self.editor.contains(p))
+    }
+
+    /// Returns whether an ancestor sets an inherited attribute
+    fn is_inherited(&self, name: &str) -> bool {
+        self.overridable
+            || self
+                .ancestors
+                .iter()
+                .any(|attrs| attrs.contains(name) || attrs.contains("style"))
+    }
+
+    fn optimize(&mut self, nodes: &mut Vec<Node>) {
+        nodes.retain(|node| match node {
+            Node::Element(elem) => elem.name != "metadata" && !self.is_editor(&elem.name),
+            _ => true,
+        });
+        for node in nodes {
+            if let Node::Element(elem) = node {
+                self.optimize_element(elem);
+            }
+        }
+    }
+
+    fn optimize_element(&mut self, elem: &mut Element) {
+        let precision = self.config.xml.uglify_svg_precision;
+        let attrs = std::mem::take(&mut elem.attrs);
+        for (key, value) in attrs {
+            let default = DEFAULT_ATTRS
+                .iter()
+                .any(|(e, a, d)| *e == elem.name && *a == key && same_value(&value, d))
+                || INHERITED_DEFAULTS
+                    .iter()
+                    .any(|(a, d)| *a == key && same_value(&value, d) && !self.is_inherited(a))
+                || (key == "opacity" && same_value(&value, "1"));
+            if default || self.is_editor(&key) {
+                continue;
+            }
+            let value = match key.as_str() {
+                "d" => shorten_path(&value, precision),
+                "style" => css::fmt_declarations(&value, self.config, true).ok(),
+                k if NUMERIC_ATTRS.contains(&k) => shorten_numbers(&value, precision),
+                _ => None,
+            }
+            .unwrap_or(value);
+            elem.attrs.push((key, value));
+        }
+        self.ancestors
+            .push(elem.attrs.iter().map(|(k, _)| k.clone()).collect());
+        self.optimize(&mut elem.children);
+        self.ancestors.pop();
+    }
+}
+
+/// Parses a number at the start of `src`, returns its length
+fn number_len(src: &[u8]) -> Option<usize> {
+    let mut i = 0;
+    if matches!(src.first(), Some(b'+' | b'-')) {
+        i += 1;
+    }
+    let digits = |i: &mut usize| {
+        let start = *i;
+        while src.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+            *i += 1;
+        }
+        *i - start
+    };
+    let mut n = digits(&mut i);
+    if src.get(i) == Some(&b'.') {
+        i += 1;
+        n += digits(&mut i);
+    }
+    if n == 0 {
+        return None;
+    }
+    if matches!(src.get(i), Some(b'e' | b'E')) {
+        let mut j = i + 1;
+        if matches!(src.get(j), Some(b'+' | b'-')) {
+            j += 1;
+        }
+        if digits(&mut j) > 0 {
+            i = j;
+        }
+    }
+    Some(i)
+}
+
+/// Writes a number in its shortest form (e.g. `0.50` becomes `.5`), rounded to `precision`
+/// decimals if set
+fn shorten_number(num: &str, precision: Option<usize>) -> String {
+    if num.contains(['e', 'E']) {
+        return num.to_owned();
+    }
+    let num = match precision.zip(num.parse::<f64>().ok()) {
+        Some((precision, value)) => format!("{value:.precision$}"),
+        None => num.to_owned(),
+    };
+    let (negative, abs) = match num.strip_prefix('-') {
+        Some(abs) => (true, abs),
+        None => (false, num.trim_start_matches('+')),
+    };
+    let (int, frac) = abs.split_once('.').unwrap_or((abs, ""));
+    let int = int.trim_start_matches('0');
+    let frac = frac.trim_end_matches('0');
+    let mut short = String::from(int);
+    if !frac.is_empty() {
+        short.push('.');
+        short.push_str(frac);
+    }
+    if short.is_empty() {
+        "0".into()
+    } else if negative {
+        format!("-{short}")
+    } else {
+        short
+    }
+}
+
+/// Appends a number to a list of numbers, separated only where needed
+fn push_number(out: &mut String, num: &str, prev: Option<&str>) {
+    if let Some(prev) = prev
+        && !num.starts_with('-')
+        && !(num.starts_with('.') && prev.contains('.') && !prev.contains(['e', 'E']))
+    {
+        out.push(' ');
+    }
+    out.push_str(num);
+}
+
+/// Shortens the numbers of a value made only of numbers, or returns None
+fn shorten_numbers(value: &str, precision: Option<usize>) -> Option<String> {
+    let mut out = String::new();
+    for num in value.split(|c: char| c.is_ascii_whitespace() || c == ',') {
+        if num.is_empty() {
+            continue;
+        }
+        if number_len(num.as_bytes()) != Some(num.len()) {
+            return None;
+        }
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(&shorten_number(num, precision));
+    }
+    Some(out)
+}
+
+/// Returns the number of arguments of a path command
+fn path_args(cmd: u8) -> Option<usize> {
+    match cmd.to_ascii_lowercase() {
+        b'z' => Some(0),
+        b'h' | b'v' => Some(1),
+        b'm' | b'l' | b't' => Some(2),
+        b's' | b'q' => Some(4),
+        b'c' => Some(6),
+        b'a' => Some(7),
+        _ => None,
+    }
+}
+
+/// Writes path data compactly: repeated commands are omitted and numbers are shortened. Returns
+/// None if the path data is invalid
+fn shorten_path(d: &str, precision: Option<usize>) -> Option<String> {
+    let src = d.as_bytes();
+    let mut i = 0;
+    let mut out = String::with_capacity(d.len());
+    let mut prev: Option<String> = None;
+    // Command assumed for numbers without a command
+    let mut implicit = None;
+    let skip = |i: &mut usize| {
+        while src
+            .get(*i)
+            .is_some_and(|c| c.is_ascii_whitespace() || *c == b',')
+        {
+            *i += 1;
+        }
+    };
+    loop {
+        skip(&mut i);
+        let Some(&cmd) = src.get(i) else {
+            break;
+        };
+        let args = path_args(cmd)?;
+        let mut cmd = cmd;
+        i += 1;
+        if args == 0 {
+            out.push(cmd as char);
+            prev = None;
+            implicit = None;
+            continue;
+        }
+        loop {
+            if implicit != Some(cmd) {
+                out.push(cmd as char);
+                prev = None;
+            }
+            implicit = Some(match cmd {
+                b'M' => b'L',
+                b'm' => b'l',
+                cmd => cmd,
+            });
+            for arg in 0..args {
+                skip(&mut i);
+                // The flags of arcs are single digits, that can be written without separator
+                let len = if cmd.eq_ignore_ascii_case(&b'a') && (arg == 3 || arg == 4) {
+                    matches!(src.get(i), Some(b'0' | b'1')).then_some(1)
+                } else {
+                    number_len(&src[i..])
+                }?;
+                let num = shorten_number(&d[i..i + len], precision);
+                push_number(&mut out, &num, prev.as_deref());
+                prev = Some(num);
+                i += len;
+            }
+            skip(&mut i);
+            if !src
+                .get(i)
+                .is_some_and(|c| matches!(c, b'+' | b'-' | b'.') || c.is_ascii_digit())
+            {
+                break;
+            }
+            // Numbers after the arguments repeat the command (`M` is followed by `L`)
+            cmd = implicit?;
+        }
+    }
+    Some(out)
+}
+
+/// Writer of the formatted document
+struct Xml<'a> {
+    config: &'a Config,
+    out: String,
+    minify: bool,
+    svg: bool,
+    /// Indentation level
+    indent: usize,
+}
+
+impl Xml<'_> {
+    fn write_indent(&mut self) {
+        self.out.push_str(&indent(self.config, self.indent));
+    }
+
+    /// Writes markup on its own line when prettifying, unless `inline`
+    fn write_block(&mut self, markup: &str, inline: bool) {
+        let block = !inline && !self.minify;
+        if block {
+            self.write_indent();
+        }
+        self.out.push_str(markup);
+        if block {
+            self.out.push('\n');
+        }
+    }
+
+    fn write_node(&mut self, node: &Node, inline: bool) -> Result<()> {
+        match node {
+            Node::Element(elem) => self.write_element(elem, inline)?,
+            Node::Text(text) if inline => self.out.push_str(text),
+            // Whitespace between elements
+            Node::Text(text) if text.trim().is_empty() => {}
+            Node::Text(text) => self.write_block(text.trim(), inline),
+            Node::CData(data) => self.write_block(&format!("<![CDATA[{data}]]>"), inline),
+            Node::Comment(_) if self.minify && self.config.xml.uglify_rm_comments => {}
+            Node::Comment(text) => self.write_block(&format!("<!--{text}-->"), inline),
+            Node::Other(markup) => self.write_block(markup, inline),
+        }
+        Ok(())
+    }
+
+    fn write_element(&mut self, elem: &Element, inline: bool) -> Result<()> {
+        let block = !inline && !self.minify;
+        if block {
+            self.write_indent();
+        }
+        self.out.push('<');
+        self.out.push_str(&elem.name);
+        for (key, value) in &elem.attrs {
+            let quote = if value.contains('"') { '\'' } else { '"' };
+            self.out.push(' ');
+            self.out.push_str(key);
+            self.out.push('=');
+            self.out.push(quote);
+            self.out.push_str(value);
+            self.out.push(quote);
+        }
+        // Descendants of inline content are inline too, so `xml:space` applies to them
+        let inline_content =
+            inline || elem.is_inline() || (self.svg && TEXT_ELEMENTS.contains(&elem.name.as_str()));
+        if !inline_content
+            && elem
+                .children
+                .iter()
+                .all(|c| matches!(c, Node::Text(text) if text.trim().is_empty()))
+        {
+            self.out.push_str("/>");
+        } else {
+            self.out.push('>');
+            if self.svg && elem.name == "style" {
+                self.write_style(elem, block)?;
+            } else if inline_content {
+                for child in &elem.children {
+                    self.write_node(child, true)?;
+                }
+            } else {
+                if block {
+                    self.out.push('\n');
+                }
+                self.indent += 1;
+                for child in &elem.children {
+                    self.write_node(child, false)?;
+                }
+                self.indent -= 1;
+                if block {
+                    self.write_indent();
+                }
+            }
+            self.out.push_str("</");
+            self.out.push_str(&elem.name);
+            self.out.push('>');
+        }
+        if block {
+            self.out.push('\n');
+        }
+        Ok(())
+    }
+
+    /// Formats the CSS of a style element, written in a CDATA section if needed
+    fn write_style(&mut self, elem: &Element, block: bool) -> Result<()> {
+        let mut src = String::new();
+        let mut cdata = false;
+        for child in &elem.children {
+            match child {
+                Node::Text(text) => src.push_str(&unescape(text).unwrap_or(Cow::Borrowed(text))),
+                Node::CData(data) => {
+                    src.push_str(data);
+                    cdata = true;
+                }
+                _ => {}
+            }
+        }
+        let css = css::fmt_str(&src, self.config, self.minify)?;
+        let cdata = cdata || css.contains(['<', '&']);
+        if !block {
+            if cdata {
+                self.out.push_str(&format!("<![CDATA[{css}]]>"));
+            } else {
+                self.out.push_str(&css);
+            }
+            return Ok(());
+        }
+        self.out.push('\n');
+        self.indent += 1;
+        if cdata {
+            self.write_block("<![CDATA[", false);
+            self.indent += 1;
+        }
+        write_lines(&mut self.out, &css, &indent(self.config, self.indent));
+        if cdata {
+            self.indent -= 1;
+            self.write_block("]]>", false);
+        }
+        self.indent -= 1;
+        self.write_indent();
+        Ok(())
+    }
+}